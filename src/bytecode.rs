@@ -0,0 +1,767 @@
+//! The `.loxc` format for compiled scripts.
+//!
+//! A file starts with a header of the magic number, the format version and a
+//! checksum of the rest of the file. The rest holds the script's source, used
+//! to show the offending line in runtime errors, followed by the script
+//! function. A function is written as its name, arity, upvalue descriptors,
+//! exception handlers, constants, and its instructions each followed by their
+//! span. Functions nested in it are written in place among its constants.
+//! Numbers are little-endian and lengths and counts are `u32`s.
+
+use std::rc::Rc;
+
+use crate::chunk::{Chunk, OpCode, Span};
+use crate::error::BytecodeError;
+use crate::gc::{Gc, GcRef};
+use crate::value::{Closure, FnUpvalue, Function, Handler, Value};
+
+const MAGIC: &[u8; 4] = b"LOXC";
+const VERSION: u16 = 1;
+const HEADER_SIZE: usize = MAGIC.len() + 2 + 4;
+
+const NUMBER_CONSTANT: u8 = 0;
+const STRING_CONSTANT: u8 = 1;
+const FUNCTION_CONSTANT: u8 = 2;
+
+/// How deeply functions can be nested in a file, so reading one can't
+/// overflow the native stack.
+const MAX_NESTING: usize = 256;
+
+/// Whether `bytes` look like a compiled script rather than source code.
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub(crate) fn serialize(function: &Function, gc: &Gc) -> Vec<u8> {
+    let mut writer = Writer {
+        gc,
+        bytes: Vec::new(),
+    };
+    writer.string(&function.chunk.source);
+    writer.function(function);
+
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + writer.bytes.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&checksum(&writer.bytes).to_le_bytes());
+    bytes.extend_from_slice(&writer.bytes);
+    bytes
+}
+
+/// Loads a script written by [`serialize`], checking that every operand
+/// refers to a constant, upvalue, local or instruction that exists and that
+/// no instruction pops more values than its frame holds, so a damaged or
+/// hand-made file can't crash the VM.
+pub(crate) fn deserialize(bytes: &[u8], gc: &mut Gc) -> Result<GcRef<Function>, BytecodeError> {
+    if !is_bytecode(bytes) {
+        return Err(BytecodeError::NotBytecode);
+    }
+
+    if bytes.len() < HEADER_SIZE {
+        return Err(BytecodeError::Truncated);
+    }
+
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != VERSION {
+        return Err(BytecodeError::UnsupportedVersion(version));
+    }
+
+    let (header, payload) = bytes.split_at(HEADER_SIZE);
+    if checksum(payload).to_le_bytes() != header[6..] {
+        return Err(BytecodeError::ChecksumMismatch);
+    }
+
+    let mut reader = Reader {
+        gc,
+        bytes: payload,
+        source: Rc::from(""),
+    };
+    reader.source = Rc::from(reader.string()?);
+
+    let function = reader.function(0, 0)?;
+    if !reader.bytes.is_empty() {
+        return invalid("unexpected data after the script.".to_owned());
+    }
+
+    if !reader.gc.deref(function).upvalues.is_empty() {
+        return invalid("the script can't capture upvalues.".to_owned());
+    }
+
+    Ok(function)
+}
+
+/// FNV-1a, which is plenty to catch truncated or corrupted files.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+fn invalid<T>(message: String) -> Result<T, BytecodeError> {
+    Err(BytecodeError::Invalid(message))
+}
+
+struct Writer<'a> {
+    gc: &'a Gc,
+    bytes: Vec<u8>,
+}
+
+impl Writer<'_> {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: usize) {
+        let value = u32::try_from(value).expect("Too large for bytecode file");
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len());
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn function(&mut self, function: &Function) {
+        let name: &String = self.gc.deref(function.name);
+        self.string(name);
+        self.u8(function.arity as u8);
+
+        self.u32(function.upvalues.len());
+        for upvalue in &function.upvalues {
+            self.u16(upvalue.index);
+            self.u8(upvalue.is_local as u8);
+        }
+
+        self.u32(function.handlers.len());
+        for handler in &function.handlers {
+            self.u32(handler.start);
+            self.u32(handler.end);
+            self.u32(handler.target);
+            self.u32(handler.depth);
+        }
+
+        self.u32(function.chunk.constants.len());
+        for &constant in &function.chunk.constants {
+            self.constant(constant);
+        }
+
+        self.u32(function.chunk.code.len());
+        for (&op_code, &span) in function.chunk.code.iter().zip(&function.chunk.spans) {
+            self.op_code(op_code);
+            self.u32(span.offset);
+            self.u32(span.length);
+            self.u32(span.line as usize);
+            self.u32(span.column as usize);
+        }
+    }
+
+    fn constant(&mut self, constant: Value) {
+        match constant {
+            Value::Number(number) => {
+                self.u8(NUMBER_CONSTANT);
+                self.bytes.extend_from_slice(&number.to_le_bytes());
+            }
+            Value::String(string) => {
+                self.u8(STRING_CONSTANT);
+                let string: &String = self.gc.deref(string);
+                self.string(string);
+            }
+            Value::Closure(closure) => {
+                self.u8(FUNCTION_CONSTANT);
+                let function = self.gc.deref(self.gc.deref(closure).function);
+                self.function(function);
+            }
+            _ => panic!("Constant can't be serialized"),
+        }
+    }
+
+    fn op_code(&mut self, op_code: OpCode) {
+        use OpCode::*;
+
+        match op_code {
+            OpConstant(operand) => {
+                self.u8(0);
+                self.u16(operand);
+            }
+            OpNil => self.u8(1),
+            OpTrue => self.u8(2),
+            OpFalse => self.u8(3),
+            OpPop => self.u8(4),
+            OpGetLocal(operand) => {
+                self.u8(5);
+                self.u16(operand);
+            }
+            OpSetLocal(operand) => {
+                self.u8(6);
+                self.u16(operand);
+            }
+            OpGetGlobal(operand) => {
+                self.u8(7);
+                self.u16(operand);
+            }
+            OpDefineGlobal(operand) => {
+                self.u8(8);
+                self.u16(operand);
+            }
+            OpSetGlobal(operand) => {
+                self.u8(9);
+                self.u16(operand);
+            }
+            OpGetUpvalue(operand) => {
+                self.u8(10);
+                self.u16(operand);
+            }
+            OpSetUpvalue(operand) => {
+                self.u8(11);
+                self.u16(operand);
+            }
+            OpGetProperty(operand) => {
+                self.u8(12);
+                self.u16(operand);
+            }
+            OpSetProperty(operand) => {
+                self.u8(13);
+                self.u16(operand);
+            }
+            OpGetSuper(operand) => {
+                self.u8(14);
+                self.u16(operand);
+            }
+            OpEqual => self.u8(15),
+            OpGreater => self.u8(16),
+            OpLess => self.u8(17),
+            OpAdd => self.u8(18),
+            OpSubtract => self.u8(19),
+            OpMultiply => self.u8(20),
+            OpDivide => self.u8(21),
+            OpNot => self.u8(22),
+            OpNegate => self.u8(23),
+            OpPrint => self.u8(24),
+            OpJump(operand) => {
+                self.u8(25);
+                self.u16(operand);
+            }
+            OpJumpIfFalse(operand) => {
+                self.u8(26);
+                self.u16(operand);
+            }
+            OpLoop(operand) => {
+                self.u8(27);
+                self.u16(operand);
+            }
+            OpCall(operand) => {
+                self.u8(28);
+                self.u8(operand);
+            }
+            OpInvoke(index, arg_count) => {
+                self.u8(29);
+                self.u16(index);
+                self.u8(arg_count);
+            }
+            OpSuperInvoke(index, arg_count) => {
+                self.u8(30);
+                self.u16(index);
+                self.u8(arg_count);
+            }
+            OpClosure(operand) => {
+                self.u8(31);
+                self.u16(operand);
+            }
+            OpCloseUpvalue => self.u8(32),
+            OpReturn => self.u8(33),
+            OpClass(operand) => {
+                self.u8(34);
+                self.u16(operand);
+            }
+            OpInherit => self.u8(35),
+            OpMethod(operand) => {
+                self.u8(36);
+                self.u16(operand);
+            }
+            OpBuildList(operand) => {
+                self.u8(37);
+                self.u16(operand);
+            }
+            OpBuildMap(operand) => {
+                self.u8(38);
+                self.u16(operand);
+            }
+            OpBuildString(operand) => {
+                self.u8(39);
+                self.u16(operand);
+            }
+            OpGetIndex => self.u8(40),
+            OpSetIndex => self.u8(41),
+            OpThrow => self.u8(42),
+            OpImport(operand) => {
+                self.u8(43);
+                self.u16(operand);
+            }
+            OpStaticMethod(operand) => {
+                self.u8(44);
+                self.u16(operand);
+            }
+            OpGetter(operand) => {
+                self.u8(45);
+                self.u16(operand);
+            }
+            OpSetter(operand) => {
+                self.u8(46);
+                self.u16(operand);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    gc: &'a mut Gc,
+    bytes: &'a [u8],
+    source: Rc<str>,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], BytecodeError> {
+        if self.bytes.len() < length {
+            return Err(BytecodeError::Truncated);
+        }
+
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, BytecodeError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, BytecodeError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn length(&mut self) -> Result<usize, BytecodeError> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn string(&mut self) -> Result<String, BytecodeError> {
+        let length = self.length()?;
+        match std::str::from_utf8(self.take(length)?) {
+            Ok(string) => Ok(string.to_owned()),
+            Err(_) => invalid("string isn't valid UTF-8.".to_owned()),
+        }
+    }
+
+    /// Reads a function nested `depth` levels deep in the script, in one that
+    /// has `enclosing_upvalues` upvalues.
+    fn function(
+        &mut self,
+        enclosing_upvalues: usize,
+        depth: usize,
+    ) -> Result<GcRef<Function>, BytecodeError> {
+        if depth > MAX_NESTING {
+            return invalid("functions nested too deeply.".to_owned());
+        }
+
+        let name = self.string()?;
+        let name = self.gc.intern(name);
+        let mut function = Function::new(name);
+        function.chunk.source = self.source.clone();
+        function.arity = self.u8()? as usize;
+
+        for _ in 0..self.length()? {
+            let index = self.u16()?;
+            let is_local = match self.u8()? {
+                0 => false,
+                1 => true,
+                flag => return invalid(format!("bad upvalue flag {}.", flag)),
+            };
+
+            if !is_local && index as usize >= enclosing_upvalues {
+                return invalid(format!("captured upvalue {} doesn't exist.", index));
+            }
+
+            function.upvalues.push(FnUpvalue { index, is_local });
+        }
+
+        for _ in 0..self.length()? {
+            function.handlers.push(Handler {
+                start: self.length()?,
+                end: self.length()?,
+                target: self.length()?,
+                depth: self.length()?,
+            });
+        }
+
+        for _ in 0..self.length()? {
+            let constant = match self.u8()? {
+                NUMBER_CONSTANT => {
+                    let bytes = self.take(8)?.try_into().unwrap();
+                    Value::Number(f64::from_le_bytes(bytes))
+                }
+                STRING_CONSTANT => {
+                    let string = self.string()?;
+                    Value::String(self.gc.intern(string))
+                }
+                FUNCTION_CONSTANT => {
+                    let nested = self.function(function.upvalues.len(), depth + 1)?;
+                    Value::Closure(self.gc.alloc(Closure::new(nested)))
+                }
+                tag => return invalid(format!("unknown constant type {}.", tag)),
+            };
+
+            function.chunk.add_constant(constant);
+        }
+
+        for _ in 0..self.length()? {
+            let op_code = self.op_code()?;
+            let span = Span {
+                offset: self.length()?,
+                length: self.length()?,
+                line: self.u32()?,
+                column: self.u32()?,
+            };
+
+            function.chunk.write(op_code, span);
+        }
+
+        validate(&function, self.gc)?;
+        Ok(self.gc.alloc(function))
+    }
+
+    fn op_code(&mut self) -> Result<OpCode, BytecodeError> {
+        use OpCode::*;
+
+        let op_code = match self.u8()? {
+            0 => OpConstant(self.u16()?),
+            1 => OpNil,
+            2 => OpTrue,
+            3 => OpFalse,
+            4 => OpPop,
+            5 => OpGetLocal(self.u16()?),
+            6 => OpSetLocal(self.u16()?),
+            7 => OpGetGlobal(self.u16()?),
+            8 => OpDefineGlobal(self.u16()?),
+            9 => OpSetGlobal(self.u16()?),
+            10 => OpGetUpvalue(self.u16()?),
+            11 => OpSetUpvalue(self.u16()?),
+            12 => OpGetProperty(self.u16()?),
+            13 => OpSetProperty(self.u16()?),
+            14 => OpGetSuper(self.u16()?),
+            15 => OpEqual,
+            16 => OpGreater,
+            17 => OpLess,
+            18 => OpAdd,
+            19 => OpSubtract,
+            20 => OpMultiply,
+            21 => OpDivide,
+            22 => OpNot,
+            23 => OpNegate,
+            24 => OpPrint,
+            25 => OpJump(self.u16()?),
+            26 => OpJumpIfFalse(self.u16()?),
+            27 => OpLoop(self.u16()?),
+            28 => OpCall(self.u8()?),
+            29 => OpInvoke(self.u16()?, self.u8()?),
+            30 => OpSuperInvoke(self.u16()?, self.u8()?),
+            31 => OpClosure(self.u16()?),
+            32 => OpCloseUpvalue,
+            33 => OpReturn,
+            34 => OpClass(self.u16()?),
+            35 => OpInherit,
+            36 => OpMethod(self.u16()?),
+            37 => OpBuildList(self.u16()?),
+            38 => OpBuildMap(self.u16()?),
+            39 => OpBuildString(self.u16()?),
+            40 => OpGetIndex,
+            41 => OpSetIndex,
+            42 => OpThrow,
+            43 => OpImport(self.u16()?),
+            44 => OpStaticMethod(self.u16()?),
+            45 => OpGetter(self.u16()?),
+            46 => OpSetter(self.u16()?),
+            tag => return invalid(format!("unknown instruction {}.", tag)),
+        };
+
+        Ok(op_code)
+    }
+}
+
+fn validate(function: &Function, gc: &Gc) -> Result<(), BytecodeError> {
+    use OpCode::*;
+
+    let chunk = &function.chunk;
+    let length = chunk.code.len();
+
+    if !matches!(chunk.code.last(), Some(OpReturn)) {
+        return invalid("function doesn't end with a return.".to_owned());
+    }
+
+    for (offset, &op_code) in chunk.code.iter().enumerate() {
+        let is_valid = match op_code {
+            OpConstant(index) => (index as usize) < chunk.constants.len(),
+            OpGetGlobal(index)
+            | OpDefineGlobal(index)
+            | OpSetGlobal(index)
+            | OpGetProperty(index)
+            | OpSetProperty(index)
+            | OpGetSuper(index)
+            | OpClass(index)
+            | OpMethod(index)
+            | OpStaticMethod(index)
+            | OpGetter(index)
+            | OpSetter(index)
+            | OpInvoke(index, _)
+            | OpSuperInvoke(index, _)
+            | OpImport(index) => matches!(constant(chunk, index), Some(Value::String(_))),
+            OpClosure(index) => matches!(constant(chunk, index), Some(Value::Closure(_))),
+            OpGetUpvalue(index) | OpSetUpvalue(index) => (index as usize) < function.upvalues.len(),
+            OpJump(jump) | OpJumpIfFalse(jump) => offset + 1 + (jump as usize) < length,
+            OpLoop(jump) => jump as usize <= offset,
+            _ => true,
+        };
+
+        if !is_valid {
+            return invalid(format!(
+                "bad operand for {:?} at offset {}.",
+                op_code, offset
+            ));
+        }
+    }
+
+    for handler in &function.handlers {
+        if handler.start > handler.end || handler.end > length || handler.target >= length {
+            return invalid("exception handler out of range.".to_owned());
+        }
+    }
+
+    validate_stack(function, gc)
+}
+
+/// Works out how many values the frame holds before each reachable
+/// instruction, and checks that locals, captured locals and the values popped
+/// by each instruction are within it. Every path to an instruction must leave
+/// the same number of values, which the compiler always does.
+fn validate_stack(function: &Function, gc: &Gc) -> Result<(), BytecodeError> {
+    use OpCode::*;
+
+    let chunk = &function.chunk;
+    let mut heights = vec![None; chunk.code.len()];
+    let mut pending = vec![(0, function.arity + 1)];
+    for handler in &function.handlers {
+        pending.push((handler.target, handler.depth + 1));
+    }
+
+    while let Some((offset, height)) = pending.pop() {
+        match heights[offset] {
+            Some(known) if known == height => continue,
+            Some(_) => return invalid(format!("inconsistent stack depth at offset {}.", offset)),
+            None => heights[offset] = Some(height),
+        }
+
+        let op_code = chunk.code[offset];
+        let is_valid = match op_code {
+            OpGetLocal(slot) | OpSetLocal(slot) => (slot as usize) < height,
+            OpClosure(index) => match constant(chunk, index) {
+                Some(Value::Closure(closure)) => {
+                    let nested = gc.deref(gc.deref(closure).function);
+                    nested
+                        .upvalues
+                        .iter()
+                        .all(|upvalue| !upvalue.is_local || (upvalue.index as usize) < height)
+                }
+                _ => false,
+            },
+            _ => true,
+        };
+        if !is_valid {
+            return invalid(format!(
+                "bad operand for {:?} at offset {}.",
+                op_code, offset
+            ));
+        }
+
+        let (pops, pushes) = stack_effect(op_code);
+        if pops > height {
+            return invalid(format!("stack underflow at offset {}.", offset));
+        }
+
+        let height = height - pops + pushes;
+        match op_code {
+            OpJump(jump) => pending.push((offset + 1 + jump as usize, height)),
+            OpJumpIfFalse(jump) => {
+                pending.push((offset + 1 + jump as usize, height));
+                pending.push((offset + 1, height));
+            }
+            OpLoop(jump) => pending.push((offset - jump as usize, height)),
+            OpReturn | OpThrow => (),
+            _ => pending.push((offset + 1, height)),
+        }
+    }
+
+    for handler in &function.handlers {
+        let covered = heights[handler.start..handler.end].iter().flatten();
+        if covered.into_iter().any(|&height| handler.depth > height) {
+            return invalid("exception handler keeps more values than the frame holds.".to_owned());
+        }
+    }
+
+    Ok(())
+}
+
+/// How many values an instruction pops and then pushes.
+fn stack_effect(op_code: OpCode) -> (usize, usize) {
+    use OpCode::*;
+
+    match op_code {
+        OpConstant(_) | OpNil | OpTrue | OpFalse | OpGetLocal(_) | OpGetGlobal(_) => (0, 1),
+        OpGetUpvalue(_) | OpClosure(_) | OpClass(_) | OpImport(_) => (0, 1),
+        OpJump(_) | OpLoop(_) => (0, 0),
+        OpPop | OpDefineGlobal(_) | OpPrint | OpCloseUpvalue | OpReturn | OpThrow => (1, 0),
+        OpSetLocal(_) | OpSetGlobal(_) | OpSetUpvalue(_) | OpGetProperty(_) => (1, 1),
+        OpNot | OpNegate | OpJumpIfFalse(_) => (1, 1),
+        OpSetProperty(_) | OpGetSuper(_) | OpEqual | OpGreater | OpLess => (2, 1),
+        OpAdd | OpSubtract | OpMultiply | OpDivide | OpGetIndex => (2, 1),
+        OpInherit | OpMethod(_) | OpStaticMethod(_) | OpGetter(_) | OpSetter(_) => (2, 1),
+        OpSetIndex => (3, 1),
+        OpCall(arg_count) | OpInvoke(_, arg_count) => (arg_count as usize + 1, 1),
+        OpSuperInvoke(_, arg_count) => (arg_count as usize + 2, 1),
+        OpBuildList(count) | OpBuildString(count) => (count as usize, 1),
+        OpBuildMap(count) => (2 * count as usize, 1),
+    }
+}
+
+fn constant(chunk: &Chunk, index: u16) -> Option<Value> {
+    chunk.constants.get(index as usize).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::table::Table;
+
+    fn compile_script(source: &str, gc: &mut Gc) -> Vec<u8> {
+        let (function, _) = compile(source, gc, &Table::new(), None);
+        let function = function.unwrap();
+        serialize(gc.deref(function), gc)
+    }
+
+    /// A script function made of `code`, for instructions the compiler would
+    /// never emit.
+    fn script(code: &[OpCode], gc: &mut Gc) -> Function {
+        let mut function = Function::new(gc.intern("script".to_owned()));
+        for &op_code in code {
+            function.chunk.write(op_code, Span::default());
+        }
+        function
+    }
+
+    /// Recomputes the checksum after the payload has been edited.
+    fn reseal(bytes: &mut [u8]) {
+        let checksum = checksum(&bytes[HEADER_SIZE..]);
+        bytes[6..HEADER_SIZE].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    #[test]
+    fn round_trips_a_script() {
+        let mut gc = Gc::quiet();
+        let bytes = compile_script(
+            "fun f(a) { return fun () { return a; }; } print f(1)();",
+            &mut gc,
+        );
+        let function = deserialize(&bytes, &mut gc).unwrap();
+        assert_eq!(serialize(gc.deref(function), &gc), bytes);
+    }
+
+    #[test]
+    fn rejects_a_truncated_file() {
+        let mut gc = Gc::quiet();
+        let mut bytes = compile_script("print 1 + 2;", &mut gc);
+        bytes.pop();
+        reseal(&mut bytes);
+        assert_eq!(deserialize(&bytes, &mut gc), Err(BytecodeError::Truncated));
+        assert_eq!(
+            deserialize(&bytes[..8], &mut gc),
+            Err(BytecodeError::Truncated)
+        );
+    }
+
+    #[test]
+    fn rejects_a_bad_checksum() {
+        let mut gc = Gc::quiet();
+        let mut bytes = compile_script("print 1 + 2;", &mut gc);
+        *bytes.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            deserialize(&bytes, &mut gc),
+            Err(BytecodeError::ChecksumMismatch)
+        );
+    }
+
+    #[test]
+    fn rejects_a_local_outside_the_frame() {
+        use OpCode::*;
+
+        let mut gc = Gc::quiet();
+        let function = script(&[OpGetLocal(1), OpPop, OpNil, OpReturn], &mut gc);
+        let bytes = serialize(&function, &gc);
+        assert_eq!(
+            deserialize(&bytes, &mut gc),
+            invalid("bad operand for OpGetLocal(1) at offset 0.".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_a_stack_underflow() {
+        use OpCode::*;
+
+        let mut gc = Gc::quiet();
+        let function = script(&[OpPop, OpPop, OpNil, OpReturn], &mut gc);
+        let bytes = serialize(&function, &gc);
+        assert_eq!(
+            deserialize(&bytes, &mut gc),
+            invalid("stack underflow at offset 1.".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_inconsistent_stack_depths() {
+        use OpCode::*;
+
+        let mut gc = Gc::quiet();
+        let code = [OpTrue, OpJumpIfFalse(1), OpNil, OpPop, OpNil, OpReturn];
+        let function = script(&code, &mut gc);
+        let bytes = serialize(&function, &gc);
+        assert_eq!(
+            deserialize(&bytes, &mut gc),
+            invalid("inconsistent stack depth at offset 3.".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_deeply_nested_functions() {
+        use OpCode::*;
+
+        let mut gc = Gc::quiet();
+        let mut function = script(&[OpNil, OpReturn], &mut gc);
+        for _ in 0..=MAX_NESTING {
+            let nested = gc.alloc(function);
+            let closure = gc.alloc(Closure::new(nested));
+            function = script(&[OpNil, OpReturn], &mut gc);
+            function.chunk.add_constant(Value::Closure(closure));
+        }
+        let bytes = serialize(&function, &gc);
+        assert_eq!(
+            deserialize(&bytes, &mut gc),
+            invalid("functions nested too deeply.".to_owned())
+        );
+    }
+}
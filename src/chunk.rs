@@ -1,22 +1,24 @@
 use crate::{gc::GcRef, value::Value};
 
+use std::rc::Rc;
+
 #[derive(Copy, Clone, Debug)]
 pub enum OpCode {
-    OpConstant(u8),
+    OpConstant(u16),
     OpNil,
     OpTrue,
     OpFalse,
     OpPop,
-    OpGetLocal(u8),
-    OpSetLocal(u8),
-    OpGetGlobal(u8),
-    OpDefineGlobal(u8),
-    OpSetGlobal(u8),
-    OpGetUpvalue(u8),
-    OpSetUpvalue(u8),
-    OpGetProperty(u8),
-    OpSetProperty(u8),
-    OpGetSuper(u8),
+    OpGetLocal(u16),
+    OpSetLocal(u16),
+    OpGetGlobal(u16),
+    OpDefineGlobal(u16),
+    OpSetGlobal(u16),
+    OpGetUpvalue(u16),
+    OpSetUpvalue(u16),
+    OpGetProperty(u16),
+    OpSetProperty(u16),
+    OpGetSuper(u16),
     OpEqual,
     OpGreater,
     OpLess,
@@ -31,21 +33,45 @@ pub enum OpCode {
     OpJumpIfFalse(u16),
     OpLoop(u16),
     OpCall(u8),
-    OpInvoke(u8, u8),
-    OpSuperInvoke(u8, u8),
-    OpClosure(u8),
+    OpInvoke(u16, u8),
+    OpSuperInvoke(u16, u8),
+    OpClosure(u16),
     OpCloseUpvalue,
     OpReturn,
-    OpClass(u8),
+    OpClass(u16),
     OpInherit,
-    OpMethod(u8),
+    OpMethod(u16),
+    OpStaticMethod(u16),
+    OpGetter(u16),
+    OpSetter(u16),
+    OpBuildList(u16),
+    OpBuildMap(u16),
+    OpBuildString(u16),
+    OpGetIndex,
+    OpSetIndex,
+    OpThrow,
+    OpImport(u16),
+}
+
+/// The part of the source a token or instruction came from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    /// Byte offset of the start in the source.
+    pub offset: usize,
+    /// Length in bytes.
+    pub length: usize,
+    pub line: u32,
+    pub column: u32,
 }
 
 #[derive(Debug)]
 pub struct Chunk {
     pub code: Vec<OpCode>,
     pub constants: Vec<Value>,
-    pub lines: Vec<u32>,
+    pub spans: Vec<Span>,
+    /// The source the chunk was compiled from, shared by every chunk of a
+    /// script. Used to show the offending line in runtime errors.
+    pub source: Rc<str>,
 }
 
 impl Chunk {
@@ -53,13 +79,14 @@ impl Chunk {
         Self {
             code: Vec::new(),
             constants: Vec::new(),
-            lines: Vec::new(),
+            spans: Vec::new(),
+            source: Rc::from(""),
         }
     }
 
-    pub fn write<T: Into<OpCode>>(&mut self, op_code: T, line: u32) -> usize {
+    pub fn write<T: Into<OpCode>>(&mut self, op_code: T, span: Span) -> usize {
         self.code.push(op_code.into());
-        self.lines.push(line);
+        self.spans.push(span);
         self.code.len() - 1
     }
 
@@ -68,17 +95,11 @@ impl Chunk {
         self.constants.len() - 1
     }
 
-    pub fn write_constant(&mut self, value: Value, line: u32) -> usize {
-        let index = self.add_constant(value);
-        self.write(OpCode::OpConstant(index as u8), line);
-        index
-    }
-
-    pub fn read_constant(&self, index: u8) -> Value {
+    pub fn read_constant(&self, index: u16) -> Value {
         self.constants[index as usize]
     }
 
-    pub fn read_string(&self, index: u8) -> GcRef<String> {
+    pub fn read_string(&self, index: u16) -> GcRef<String> {
         if let Value::String(s) = self.read_constant(index) {
             s
         } else {
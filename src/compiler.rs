@@ -1,23 +1,41 @@
-use crate::chunk::{Chunk, OpCode};
+use crate::chunk::{Chunk, OpCode, Span};
+use crate::error::{CompileError, CompileWarning, Excerpt};
 use crate::gc::{Gc, GcRef};
-use crate::scanner::{Scanner, Token, TokenType};
-use crate::value::{Closure, FnUpvalue, Function, Value};
+use crate::scanner::{unescape, Scanner, Token, TokenType};
+use crate::table::Table;
+use crate::value::{Closure, FnUpvalue, Function, Handler, Module, Value};
 
-#[cfg(feature = "debug_print_code")]
-use crate::debug::Disassembler;
-
-use std::mem;
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::{iter, mem};
 
 pub struct Parser<'a> {
     gc: &'a mut Gc,
+    source: Rc<str>,
     scanner: Scanner<'a>,
     previous: Token<'a>,
     current: Token<'a>,
     compiler: Box<Compiler<'a>>,
     current_class: Option<ClassCompiler>,
-    had_error: bool,
+    diagnostics: Diagnostics,
+    /// Globals already defined in the VM, e.g. by an earlier REPL line.
+    globals: &'a Table,
+    /// The module being compiled, or `None` for the VM's main script.
+    module: Option<GcRef<Module>>,
+    /// Names of the globals this script defines.
+    global_definitions: HashSet<&'a str>,
+    /// Assignments to globals, checked against the definitions at the end.
+    global_assignments: Vec<(&'a str, Span)>,
+}
+
+/// The errors and warnings found while compiling.
+struct Diagnostics {
+    source: Rc<str>,
+    errors: Vec<CompileError>,
+    warnings: Vec<CompileWarning>,
+    /// Set after a syntax error until the parser has synchronized, so that
+    /// errors caused only by the parser being lost aren't reported.
     panic_mode: bool,
-    errors: Vec<&'static str>,
 }
 
 struct Compiler<'a> {
@@ -26,6 +44,10 @@ struct Compiler<'a> {
     scope_depth: i32,
     function: Function,
     function_type: FunctionType,
+    tries: Vec<TryCompiler>,
+    loops: Vec<LoopCompiler>,
+    /// Set after a statement that never completes normally, like `return`.
+    after_jump: bool,
 }
 
 #[derive(Clone)]
@@ -38,16 +60,118 @@ struct Local<'a> {
     name: &'a str,
     depth: i32,
     is_captured: bool,
+    is_used: bool,
+    span: Span,
 }
 
 impl<'a> Local<'a> {
-    fn new(name: &'a str, depth: i32) -> Self {
+    fn new(name: &'a str, depth: i32, span: Span) -> Self {
         Self {
             name,
             depth,
             is_captured: false,
+            is_used: false,
+            span,
         }
     }
+
+    /// Whether the variable is declared by the user, as opposed to the hidden
+    /// locals the compiler reserves, and hasn't opted out of warnings with a
+    /// leading underscore.
+    fn is_checked(&self) -> bool {
+        !matches!(self.name, "" | "this" | "super") && !self.name.starts_with('_')
+    }
+}
+
+impl Diagnostics {
+    fn new(source: Rc<str>) -> Self {
+        Self {
+            source,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            panic_mode: false,
+        }
+    }
+
+    /// Records an error that leaves the parser where it expects to be, so
+    /// compiling can go on and find more errors.
+    fn error(&mut self, token: &Token, message: &str) {
+        if self.panic_mode {
+            return;
+        }
+
+        let lexeme = match token.kind {
+            TokenType::Error => None,
+            TokenType::String => Some(format!("\"{}\"", token.value)),
+            _ => Some(token.value.to_owned()),
+        };
+
+        self.errors.push(CompileError {
+            line: token.line,
+            column: token.column,
+            lexeme,
+            message: message.to_owned(),
+            excerpt: Excerpt::new(&self.source, token.span()),
+        });
+    }
+
+    /// Records an error after which the parser is lost, suppressing further
+    /// errors until it has synchronized.
+    fn syntax_error(&mut self, token: &Token, message: &str) {
+        self.error(token, message);
+        self.panic_mode = true;
+    }
+
+    fn warning(&mut self, lexeme: &str, span: Span, message: &str) {
+        self.warnings.push(CompileWarning {
+            line: span.line,
+            column: span.column,
+            lexeme: Some(lexeme.to_owned()),
+            message: message.to_owned(),
+            excerpt: Excerpt::new(&self.source, span),
+        });
+    }
+
+    fn warn_unused(&mut self, locals: &[Local]) {
+        for local in locals {
+            if local.is_checked() && !local.is_used {
+                self.warning(local.name, local.span, "Local variable is never used.");
+            }
+        }
+    }
+}
+
+/// A `try` statement whose `finally` block hasn't been compiled yet. Code
+/// leaving it early records why in two hidden locals and jumps to the
+/// `finally` block, which then finishes what was interrupted.
+struct TryCompiler {
+    /// Slot of the local holding the completion kind. The next slot holds the
+    /// thrown or returned value.
+    completion: u16,
+    /// The number of locals in scope when the `try` block started.
+    depth: usize,
+    exits: Vec<usize>,
+    /// The ways the `try` statement was left early, to finish after `finally`.
+    pending: Vec<Completion>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Completion {
+    Throw = 1,
+    Return,
+    Break,
+    Continue,
+}
+
+/// A loop whose body is being compiled, for `break` and `continue`.
+struct LoopCompiler {
+    /// Where `continue` jumps back to.
+    start: usize,
+    /// The number of locals in scope outside the loop body.
+    depth: usize,
+    /// The number of enclosing `try` statements outside the loop.
+    tries: usize,
+    breaks: Vec<usize>,
 }
 
 impl ClassCompiler {
@@ -64,6 +188,7 @@ enum FunctionType {
     Function,
     Method,
     Initializer,
+    Setter,
     Script,
 }
 
@@ -75,12 +200,15 @@ impl<'a> Compiler<'a> {
             scope_depth: 0,
             function: Function::new(name),
             function_type: ftype,
+            tries: Vec::new(),
+            loops: Vec::new(),
+            after_jump: false,
         };
 
         let local = if ftype != FunctionType::Function {
-            Local::new("this", 0)
+            Local::new("this", 0, Span::default())
         } else {
-            Local::new("", 0)
+            Local::new("", 0, Span::default())
         };
 
         compiler.locals.push(local);
@@ -88,48 +216,65 @@ impl<'a> Compiler<'a> {
         Box::new(compiler)
     }
 
-    fn resolve_local(&mut self, name: &str, errors: &mut Vec<&'static str>) -> Option<u8> {
-        for (i, local) in self.locals.iter().enumerate().rev() {
+    fn resolve_local(
+        &mut self,
+        name: &str,
+        token: &Token,
+        diagnostics: &mut Diagnostics,
+    ) -> Option<u16> {
+        for (i, local) in self.locals.iter_mut().enumerate().rev() {
             if name == local.name {
                 if local.depth == -1 {
-                    errors.push("Can't read local variable in its own initializer.");
+                    diagnostics.error(token, "Can't read local variable in its own initializer.");
                 }
 
-                return Some(i as u8);
+                local.is_used = true;
+                return Some(i as u16);
             }
         }
 
         None
     }
 
-    fn resolve_upvalue(&mut self, name: &str, errors: &mut Vec<&'static str>) -> Option<u8> {
+    fn resolve_upvalue(
+        &mut self,
+        name: &str,
+        token: &Token,
+        diagnostics: &mut Diagnostics,
+    ) -> Option<u16> {
         if let Some(enclosing) = self.enclosing.as_mut() {
-            if let Some(local) = enclosing.resolve_local(name, errors) {
+            if let Some(local) = enclosing.resolve_local(name, token, diagnostics) {
                 enclosing.locals[local as usize].is_captured = true;
-                return Some(self.add_upvalue(local, true, errors));
+                return Some(self.add_upvalue(local, true, token, diagnostics));
             }
 
-            if let Some(upvalue) = enclosing.resolve_upvalue(name, errors) {
-                return Some(self.add_upvalue(upvalue, false, errors));
+            if let Some(upvalue) = enclosing.resolve_upvalue(name, token, diagnostics) {
+                return Some(self.add_upvalue(upvalue, false, token, diagnostics));
             }
         }
 
         None
     }
 
-    fn add_upvalue(&mut self, index: u8, is_local: bool, errors: &mut Vec<&'static str>) -> u8 {
+    fn add_upvalue(
+        &mut self,
+        index: u16,
+        is_local: bool,
+        token: &Token,
+        diagnostics: &mut Diagnostics,
+    ) -> u16 {
         for (i, upvalue) in self.function.upvalues.iter().enumerate() {
             if upvalue.index == index && upvalue.is_local == is_local {
-                return i as u8;
+                return i as u16;
             }
         }
 
-        if self.function.upvalues.len() == 256 {
-            errors.push("Too many closure variables in function.");
+        if self.function.upvalues.len() == u16::MAX as usize + 1 {
+            diagnostics.syntax_error(token, "Too many closure variables in function.");
         }
 
         self.function.upvalues.push(FnUpvalue { index, is_local });
-        self.function.upvalues.len() as u8 - 1
+        self.function.upvalues.len() as u16 - 1
     }
 
     fn is_local_declared(&self, name: &str) -> bool {
@@ -145,67 +290,126 @@ impl<'a> Compiler<'a> {
 
         false
     }
+
+    /// Whether a local variable named `name` is declared in an enclosing
+    /// scope, in this function or a function it is nested in.
+    fn is_shadowing(&self, name: &str) -> bool {
+        let outer_locals = self
+            .locals
+            .iter()
+            .filter(|local| local.depth < self.scope_depth);
+        let enclosing_locals = iter::successors(self.enclosing.as_deref(), |compiler| {
+            compiler.enclosing.as_deref()
+        })
+        .flat_map(|compiler| compiler.locals.iter());
+
+        outer_locals
+            .chain(enclosing_locals)
+            .any(|local| local.is_checked() && local.name == name)
+    }
 }
 
 impl<'a> Parser<'a> {
-    fn new(source: &'a str, gc: &'a mut Gc) -> Self {
+    fn new(
+        source: &'a str,
+        gc: &'a mut Gc,
+        globals: &'a Table,
+        module: Option<GcRef<Module>>,
+    ) -> Self {
         let function_name = gc.intern("script".to_owned());
+        let mut compiler = Compiler::new(FunctionType::Script, function_name);
+        let shared_source: Rc<str> = Rc::from(source);
+        compiler.function.chunk.source = shared_source.clone();
+        compiler.function.module = module;
 
         Self {
             gc,
+            source: shared_source.clone(),
             scanner: Scanner::from(source),
             previous: Token::default(),
             current: Token::default(),
-            compiler: Compiler::new(FunctionType::Script, function_name),
+            compiler,
             current_class: None,
-            had_error: false,
-            panic_mode: false,
-            errors: Vec::new(),
+            diagnostics: Diagnostics::new(shared_source),
+            globals,
+            module,
+            global_definitions: HashSet::new(),
+            global_assignments: Vec::new(),
         }
     }
 
-    fn compile(mut self) -> Option<GcRef<Function>> {
+    fn compile(mut self) -> Compilation {
         self.advance();
 
         while !self.matches(TokenType::Eof) {
+            self.warn_unreachable();
             self.declaration();
         }
 
         self.emit_return();
-        if self.had_error {
-            None
+        self.warn_undefined_globals();
+
+        let mut warnings = self.diagnostics.warnings;
+        warnings.sort_by_key(|warning| (warning.line, warning.column));
+
+        let result = if self.diagnostics.errors.is_empty() {
+            Ok(self.gc.alloc(self.compiler.function))
         } else {
-            let function = self.gc.alloc(self.compiler.function);
-            Some(function)
+            Err(self.diagnostics.errors)
+        };
+
+        (result, warnings)
+    }
+
+    fn warn_undefined_globals(&mut self) {
+        for &(name, span) in &self.global_assignments {
+            let is_defined = self.global_definitions.contains(name)
+                || self
+                    .gc
+                    .find_string(name)
+                    .is_some_and(|name| self.globals.contains_key(&name));
+
+            if !is_defined {
+                self.diagnostics.warning(
+                    name,
+                    span,
+                    "Assignment to a global variable that is never defined.",
+                );
+            }
         }
     }
 
-    fn push_compiler(&mut self, ftype: FunctionType) {
-        let name = self.gc.intern(self.previous.value.to_owned());
-        let new_compiler = Compiler::new(ftype, name);
+    /// Warns about the statement starting at the current token if the code
+    /// before it never completes normally. Warns only once per run of dead
+    /// statements.
+    fn warn_unreachable(&mut self) {
+        if mem::take(&mut self.compiler.after_jump) {
+            let span = self.current.span();
+            self.diagnostics
+                .warning(self.current.value, span, "Unreachable code.");
+        }
+    }
+
+    fn push_compiler(&mut self, ftype: FunctionType, name: &str) {
+        let name = self.gc.intern(name.to_owned());
+        let mut new_compiler = Compiler::new(ftype, name);
+        new_compiler.function.chunk.source = self.source.clone();
+        new_compiler.function.module = self.module;
         let old_compiler = mem::replace(&mut self.compiler, new_compiler);
         self.compiler.enclosing = Some(old_compiler);
     }
 
     fn pop_compiler(&mut self) -> Function {
         self.emit_return();
+        self.diagnostics.warn_unused(&self.compiler.locals);
 
-        let function = match self.compiler.enclosing.take() {
+        match self.compiler.enclosing.take() {
             Some(enclosing) => {
                 let compiler = mem::replace(&mut self.compiler, enclosing);
                 compiler.function
             }
             None => panic!("No enclosing compiler for script"),
-        };
-
-        #[cfg(feature = "debug_print_code")]
-        if !self.had_error {
-            let disassmebler = Disassembler::new(self.gc, &function.chunk);
-            disassmebler.disassemble_chunk(function.name);
-            println!();
         }
-
-        function
     }
 
     fn advance(&mut self) {
@@ -252,8 +456,14 @@ impl<'a> Parser<'a> {
     }
 
     fn emit_byte<T: Into<OpCode>>(&mut self, op_code: T) -> usize {
-        let line = self.previous.line;
-        self.chunk_mut().write(op_code, line)
+        let span = self.previous.span();
+        self.chunk_mut().write(op_code, span)
+    }
+
+    /// Emits an instruction whose runtime errors should point at `span`
+    /// rather than at the last token parsed.
+    fn emit_byte_at<T: Into<OpCode>>(&mut self, op_code: T, span: Span) -> usize {
+        self.chunk_mut().write(op_code, span)
     }
 
     fn emit_bytes<T: Into<OpCode>, U: Into<OpCode>>(&mut self, op_code1: T, op_code2: U) -> usize {
@@ -273,11 +483,86 @@ impl<'a> Parser<'a> {
     fn emit_return(&mut self) {
         if self.compiler.function_type == FunctionType::Initializer {
             self.emit_byte(OpCode::OpGetLocal(0));
+        } else if self.compiler.function_type == FunctionType::Setter {
+            self.emit_byte(OpCode::OpGetLocal(1));
         } else {
             self.emit_byte(OpCode::OpNil);
         }
 
-        self.emit_byte(OpCode::OpReturn);
+        self.emit_unwinding_return();
+    }
+
+    /// Returns the value on top of the stack, running the `finally` block of
+    /// the innermost enclosing `try` statement first if there is one.
+    fn emit_unwinding_return(&mut self) {
+        match self.compiler.tries.last() {
+            Some(try_compiler) => {
+                let completion = try_compiler.completion;
+                self.emit_bytes(OpCode::OpSetLocal(completion + 1), OpCode::OpPop);
+                self.emit_try_exit(Completion::Return);
+            }
+            None => {
+                self.emit_byte(OpCode::OpReturn);
+            }
+        }
+    }
+
+    /// Leaves the innermost loop, running the `finally` blocks of any `try`
+    /// statements inside it first.
+    fn emit_loop_exit(&mut self, kind: Completion) {
+        let Some(loop_compiler) = self.compiler.loops.last() else {
+            return;
+        };
+
+        if self.compiler.tries.len() > loop_compiler.tries {
+            self.emit_try_exit(kind);
+            return;
+        }
+
+        let start = loop_compiler.start;
+        self.emit_pops(loop_compiler.depth);
+        if kind == Completion::Continue {
+            self.emit_loop(start);
+        } else {
+            let exit = self.emit_byte(OpCode::OpJump(0xffff));
+            if let Some(loop_compiler) = self.compiler.loops.last_mut() {
+                loop_compiler.breaks.push(exit);
+            }
+        }
+    }
+
+    /// Jumps to the `finally` block of the innermost `try` statement, which
+    /// finishes the jump once it has run.
+    fn emit_try_exit(&mut self, kind: Completion) {
+        let Some(try_compiler) = self.compiler.tries.last_mut() else {
+            return;
+        };
+
+        if !try_compiler.pending.contains(&kind) {
+            try_compiler.pending.push(kind);
+        }
+        let completion = try_compiler.completion;
+        let depth = try_compiler.depth;
+
+        self.emit_constant((kind as u8 as f64).into());
+        self.emit_bytes(OpCode::OpSetLocal(completion), OpCode::OpPop);
+        self.emit_pops(depth);
+
+        let exit = self.emit_byte(OpCode::OpJump(0xffff));
+        if let Some(try_compiler) = self.compiler.tries.last_mut() {
+            try_compiler.exits.push(exit);
+        }
+    }
+
+    /// Discards the locals above `depth` without taking them out of scope.
+    fn emit_pops(&mut self, depth: usize) {
+        for i in (depth..self.compiler.locals.len()).rev() {
+            if self.compiler.locals[i].is_captured {
+                self.emit_byte(OpCode::OpCloseUpvalue);
+            } else {
+                self.emit_byte(OpCode::OpPop);
+            }
+        }
     }
 
     fn emit_constant(&mut self, value: Value) {
@@ -285,10 +570,10 @@ impl<'a> Parser<'a> {
         self.emit_byte(OpCode::OpConstant(index));
     }
 
-    fn make_constant(&mut self, value: Value) -> u8 {
+    fn make_constant(&mut self, value: Value) -> u16 {
         let index = self.chunk_mut().add_constant(value);
 
-        match u8::try_from(index) {
+        match u16::try_from(index) {
             Ok(index) => index,
             Err(_) => {
                 self.error("Too many constants in one chunk.");
@@ -324,12 +609,20 @@ impl<'a> Parser<'a> {
     fn statement(&mut self) {
         if self.matches(TokenType::Print) {
             self.print_statement();
+        } else if self.matches(TokenType::Break) {
+            self.break_statement();
+        } else if self.matches(TokenType::Continue) {
+            self.continue_statement();
         } else if self.matches(TokenType::For) {
             self.for_statement();
         } else if self.matches(TokenType::If) {
             self.if_statement();
         } else if self.matches(TokenType::Return) {
             self.return_statement();
+        } else if self.matches(TokenType::Throw) {
+            self.throw_statement();
+        } else if self.matches(TokenType::Try) {
+            self.try_statement();
         } else if self.matches(TokenType::While) {
             self.while_statement();
         } else if self.matches(TokenType::LeftBrace) {
@@ -349,19 +642,193 @@ impl<'a> Parser<'a> {
 
     fn return_statement(&mut self) {
         if let FunctionType::Script = self.compiler.function_type {
-            self.error("Can't return from top-level code.");
+            self.semantic_error("Can't return from top-level code.");
         }
 
         if self.matches(TokenType::Semicolon) {
             self.emit_return();
         } else {
             if self.compiler.function_type == FunctionType::Initializer {
-                self.error("Can't return a value from an initializer.");
+                self.semantic_error("Can't return a value from an initializer.");
+            } else if self.compiler.function_type == FunctionType::Setter {
+                self.semantic_error("Can't return a value from a setter.");
             }
 
             self.expression();
             self.consume(TokenType::Semicolon, "Expect ';' after return value.");
-            self.emit_byte(OpCode::OpReturn);
+            self.emit_unwinding_return();
+        }
+
+        self.compiler.after_jump = true;
+    }
+
+    fn throw_statement(&mut self) {
+        self.expression();
+        self.consume(TokenType::Semicolon, "Expect ';' after thrown value.");
+        self.emit_byte(OpCode::OpThrow);
+        self.compiler.after_jump = true;
+    }
+
+    fn try_statement(&mut self) {
+        self.begin_scope();
+
+        let completion = self.compiler.locals.len() as u16;
+        for _ in 0..2 {
+            self.emit_byte(OpCode::OpNil);
+            self.add_local("");
+            self.mark_initialized();
+        }
+
+        let depth = self.compiler.locals.len();
+        self.compiler.tries.push(TryCompiler {
+            completion,
+            depth,
+            exits: Vec::new(),
+            pending: Vec::new(),
+        });
+
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'.");
+        let start = self.chunk().code.len();
+        self.begin_scope();
+        self.block();
+        self.end_scope();
+        // Any statement in the block may throw, so what follows it is always
+        // reachable.
+        self.compiler.after_jump = false;
+
+        let mut end = self.chunk().code.len();
+        let mut finally_jumps = vec![self.emit_byte(OpCode::OpJump(0xffff))];
+
+        let has_catch = self.matches(TokenType::Catch);
+        if has_catch {
+            let target = self.chunk().code.len();
+            self.compiler.function.handlers.push(Handler {
+                start,
+                end,
+                target,
+                depth,
+            });
+
+            self.begin_scope();
+            self.consume(TokenType::LeftParen, "Expect '(' after 'catch'.");
+            self.consume(TokenType::Identifier, "Expect exception variable name.");
+            self.add_local(self.previous.value);
+            self.mark_initialized();
+            self.mark_used();
+            self.consume(
+                TokenType::RightParen,
+                "Expect ')' after exception variable.",
+            );
+            self.consume(TokenType::LeftBrace, "Expect '{' before catch body.");
+            self.block();
+            self.end_scope();
+            self.compiler.after_jump = false;
+
+            end = self.chunk().code.len();
+            finally_jumps.push(self.emit_byte(OpCode::OpJump(0xffff)));
+        }
+
+        let try_compiler = self.compiler.tries.pop().unwrap();
+
+        let has_finally = self.matches(TokenType::Finally);
+        if has_finally {
+            let target = self.chunk().code.len();
+            self.compiler.function.handlers.push(Handler {
+                start,
+                end,
+                target,
+                depth,
+            });
+
+            self.emit_bytes(OpCode::OpSetLocal(completion + 1), OpCode::OpPop);
+            self.emit_constant((Completion::Throw as u8 as f64).into());
+            self.emit_bytes(OpCode::OpSetLocal(completion), OpCode::OpPop);
+        } else if !has_catch {
+            self.error_at_current("Expect 'catch' or 'finally' after try block.");
+        }
+
+        for jump in finally_jumps.into_iter().chain(try_compiler.exits) {
+            self.patch_jump(jump);
+        }
+
+        if has_finally {
+            self.consume(TokenType::LeftBrace, "Expect '{' after 'finally'.");
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+            self.compiler.after_jump = false;
+
+            self.emit_completion(completion, Completion::Throw);
+        }
+
+        for kind in try_compiler.pending {
+            self.emit_completion(completion, kind);
+        }
+
+        self.end_scope();
+    }
+
+    /// Finishes what was interrupted by the `finally` block if the `try`
+    /// statement was left with the given completion kind.
+    fn emit_completion(&mut self, completion: u16, kind: Completion) {
+        self.emit_byte(OpCode::OpGetLocal(completion));
+        self.emit_constant((kind as u8 as f64).into());
+        self.emit_byte(OpCode::OpEqual);
+        let skip_jump = self.emit_byte(OpCode::OpJumpIfFalse(0xffff));
+        self.emit_byte(OpCode::OpPop);
+
+        match kind {
+            Completion::Throw => {
+                self.emit_bytes(OpCode::OpGetLocal(completion + 1), OpCode::OpThrow);
+            }
+            Completion::Return => {
+                self.emit_byte(OpCode::OpGetLocal(completion + 1));
+                self.emit_unwinding_return();
+            }
+            Completion::Break | Completion::Continue => self.emit_loop_exit(kind),
+        }
+
+        self.patch_jump(skip_jump);
+        self.emit_byte(OpCode::OpPop);
+    }
+
+    fn break_statement(&mut self) {
+        if self.compiler.loops.is_empty() {
+            self.semantic_error("Can't use 'break' outside of a loop.");
+        }
+
+        self.consume(TokenType::Semicolon, "Expect ';' after 'break'.");
+        self.emit_loop_exit(Completion::Break);
+        self.compiler.after_jump = true;
+    }
+
+    fn continue_statement(&mut self) {
+        if self.compiler.loops.is_empty() {
+            self.semantic_error("Can't use 'continue' outside of a loop.");
+        }
+
+        self.consume(TokenType::Semicolon, "Expect ';' after 'continue'.");
+        self.emit_loop_exit(Completion::Continue);
+        self.compiler.after_jump = true;
+    }
+
+    fn begin_loop(&mut self, start: usize) {
+        self.compiler.loops.push(LoopCompiler {
+            start,
+            depth: self.compiler.locals.len(),
+            tries: self.compiler.tries.len(),
+            breaks: Vec::new(),
+        });
+    }
+
+    fn end_loop(&mut self) {
+        // The loop may run its body zero times or be left with `break`.
+        self.compiler.after_jump = false;
+
+        if let Some(loop_compiler) = self.compiler.loops.pop() {
+            for exit in loop_compiler.breaks {
+                self.patch_jump(exit);
+            }
         }
     }
 
@@ -400,6 +867,7 @@ impl<'a> Parser<'a> {
             self.patch_jump(body_jump);
         }
 
+        self.begin_loop(loop_start);
         self.statement();
         self.emit_loop(loop_start);
 
@@ -408,6 +876,7 @@ impl<'a> Parser<'a> {
             self.emit_byte(OpCode::OpPop);
         }
 
+        self.end_loop();
         self.end_scope();
     }
 
@@ -420,6 +889,7 @@ impl<'a> Parser<'a> {
         self.emit_byte(OpCode::OpPop);
 
         self.statement();
+        let then_jumps = mem::take(&mut self.compiler.after_jump);
 
         let else_jump = self.emit_byte(OpCode::OpJump(0xffff));
 
@@ -430,6 +900,7 @@ impl<'a> Parser<'a> {
             self.statement();
         }
 
+        self.compiler.after_jump &= then_jumps;
         self.patch_jump(else_jump);
     }
 
@@ -443,11 +914,13 @@ impl<'a> Parser<'a> {
         let exit_jump = self.emit_byte(OpCode::OpJumpIfFalse(0xffff));
         self.emit_byte(OpCode::OpPop);
 
+        self.begin_loop(loop_start);
         self.statement();
         self.emit_loop(loop_start);
 
         self.patch_jump(exit_jump);
         self.emit_byte(OpCode::OpPop);
+        self.end_loop();
     }
 
     fn declaration(&mut self) {
@@ -457,11 +930,15 @@ impl<'a> Parser<'a> {
             self.function_declaration();
         } else if self.matches(TokenType::Var) {
             self.var_declaration();
+        } else if self.matches(TokenType::Import) {
+            self.import_declaration();
+        } else if self.matches(TokenType::From) {
+            self.selective_import_declaration();
         } else {
             self.statement();
         }
 
-        if self.panic_mode {
+        if self.diagnostics.panic_mode {
             self.synchronize();
         }
     }
@@ -483,6 +960,48 @@ impl<'a> Parser<'a> {
         self.define_variable(global);
     }
 
+    fn import_declaration(&mut self) {
+        let (path, span) = self.module_path();
+        if self.check(TokenType::Identifier) && self.current.value == "as" {
+            self.advance();
+        } else {
+            self.error_at_current("Expect 'as' after module path.");
+        }
+
+        let global = self.parse_variable("Expect module name.");
+        self.emit_byte_at(OpCode::OpImport(path), span);
+        self.consume(TokenType::Semicolon, "Expect ';' after import.");
+        self.define_variable(global);
+    }
+
+    fn selective_import_declaration(&mut self) {
+        let (path, span) = self.module_path();
+        self.consume(TokenType::Import, "Expect 'import' after module path.");
+
+        loop {
+            let global = self.parse_variable("Expect name to import.");
+            let name = self.identifier_constant(self.previous.value);
+            self.emit_byte_at(OpCode::OpImport(path), span);
+            self.emit_byte(OpCode::OpGetProperty(name));
+            self.define_variable(global);
+
+            if !self.matches(TokenType::Comma) {
+                break;
+            }
+        }
+
+        self.consume(TokenType::Semicolon, "Expect ';' after imported names.");
+    }
+
+    fn module_path(&mut self) -> (u16, Span) {
+        self.consume(TokenType::String, "Expect module path.");
+        let path = self.gc.intern(unescape(self.previous.value));
+        (
+            self.make_constant(Value::String(path)),
+            self.previous.span(),
+        )
+    }
+
     fn function_declaration(&mut self) {
         let global = self.parse_variable("Expect function name");
         self.mark_initialized();
@@ -492,6 +1011,7 @@ impl<'a> Parser<'a> {
 
     fn block(&mut self) {
         while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            self.warn_unreachable();
             self.declaration();
         }
 
@@ -499,10 +1019,29 @@ impl<'a> Parser<'a> {
     }
 
     fn function(&mut self, ftype: FunctionType) {
-        self.push_compiler(ftype);
+        self.push_compiler(ftype, self.previous.value);
+        self.function_body();
+    }
+
+    /// Compiles the parameters and body of the function whose compiler was
+    /// just pushed, and emits its closure.
+    fn function_body(&mut self) {
         self.begin_scope();
 
         self.consume(TokenType::LeftParen, "Expect '(' after function name.");
+        self.parameters();
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.");
+        if self.compiler.function_type == FunctionType::Setter && self.compiler.function.arity != 1
+        {
+            self.semantic_error("A setter must have exactly one parameter.");
+        }
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body.");
+        self.block();
+
+        self.end_function();
+    }
+
+    fn parameters(&mut self) {
         if !self.check(TokenType::RightParen) {
             loop {
                 if self.compiler.function.arity == 255 {
@@ -513,17 +1052,16 @@ impl<'a> Parser<'a> {
 
                 let constant = self.parse_variable("Expect parameter name.");
                 self.define_variable(constant);
+                self.mark_used();
 
                 if !self.matches(TokenType::Comma) {
                     break;
                 }
             }
         }
+    }
 
-        self.consume(TokenType::RightParen, "Expect ')' after parameters.");
-        self.consume(TokenType::LeftBrace, "Expect '{' before function body.");
-        self.block();
-
+    fn end_function(&mut self) {
         let function = self.pop_compiler();
         let function_id = self.gc.alloc(function);
         let closure = Closure::new(function_id);
@@ -533,17 +1071,38 @@ impl<'a> Parser<'a> {
         self.emit_byte(OpCode::OpClosure(index));
     }
 
+    /// Compiles a method, a static method prefixed with `class`, a getter
+    /// without a parameter list or a setter with `=` after its name.
     fn method(&mut self) {
+        let is_static = self.matches(TokenType::Class);
         self.consume(TokenType::Identifier, "Expect method name.");
-        let constant = self.identifier_constant(self.previous.value);
-        let ftype = if self.previous.value == "init" {
-            FunctionType::Initializer
-        } else {
-            FunctionType::Method
-        };
+        let name = self.previous.value;
+        let constant = self.identifier_constant(name);
 
-        self.function(ftype);
-        self.emit_byte(OpCode::OpMethod(constant));
+        if is_static {
+            self.function(FunctionType::Method);
+            self.emit_byte(OpCode::OpStaticMethod(constant));
+        } else if self.check(TokenType::LeftBrace) {
+            self.push_compiler(FunctionType::Method, name);
+            self.begin_scope();
+            self.consume(TokenType::LeftBrace, "Expect '{' before getter body.");
+            self.block();
+            self.end_function();
+            self.emit_byte(OpCode::OpGetter(constant));
+        } else if self.matches(TokenType::Equal) {
+            self.push_compiler(FunctionType::Setter, name);
+            self.function_body();
+            self.emit_byte(OpCode::OpSetter(constant));
+        } else {
+            let ftype = if name == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+
+            self.function(ftype);
+            self.emit_byte(OpCode::OpMethod(constant));
+        }
     }
 
     fn class_declaration(&mut self) {
@@ -564,7 +1123,7 @@ impl<'a> Parser<'a> {
             self.variable(false);
 
             if class_name == self.previous.value {
-                self.error("A class can't inherit from itself.");
+                self.semantic_error("A class can't inherit from itself.");
             }
 
             self.begin_scope();
@@ -605,6 +1164,15 @@ impl<'a> Parser<'a> {
     fn end_scope(&mut self) {
         self.compiler.scope_depth -= 1;
 
+        let scope_start = self
+            .compiler
+            .locals
+            .iter()
+            .rposition(|local| local.depth <= self.compiler.scope_depth)
+            .map_or(0, |i| i + 1);
+        self.diagnostics
+            .warn_unused(&self.compiler.locals[scope_start..]);
+
         for i in (0..self.compiler.locals.len()).rev() {
             if self.compiler.locals[i].depth > self.compiler.scope_depth {
                 if self.compiler.locals[i].is_captured {
@@ -618,21 +1186,98 @@ impl<'a> Parser<'a> {
     }
 
     fn grouping(&mut self, _can_assign: bool) {
+        if self.at_arrow_parameters() {
+            return self.arrow_function();
+        }
+
         self.expression();
         self.consume(TokenType::RightParen, "Expect ')' after expression.");
     }
 
+    /// Whether the `(` just consumed starts the parameters of an arrow
+    /// function rather than a grouping, found by scanning ahead for the `=>`
+    /// after the closing parenthesis.
+    fn at_arrow_parameters(&self) -> bool {
+        use TokenType::*;
+
+        let mut tokens =
+            iter::once(self.current.kind).chain(self.scanner.clone().map(|token| token.kind));
+
+        let mut next = tokens.next();
+        if next == Some(Identifier) {
+            next = loop {
+                match tokens.next() {
+                    Some(Comma) if tokens.next() == Some(Identifier) => (),
+                    other => break other,
+                }
+            };
+        }
+
+        next == Some(RightParen) && tokens.next() == Some(Arrow)
+    }
+
+    fn arrow_function(&mut self) {
+        self.push_compiler(FunctionType::Function, "lambda");
+        self.begin_scope();
+
+        self.parameters();
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.");
+        self.consume(TokenType::Arrow, "Expect '=>' after parameters.");
+        self.expression();
+        self.emit_byte(OpCode::OpReturn);
+
+        self.end_function();
+    }
+
+    fn lambda(&mut self, _can_assign: bool) {
+        self.push_compiler(FunctionType::Function, "lambda");
+        self.function_body();
+    }
+
     fn number(&mut self, _can_assign: bool) {
         let value: f64 = self.previous.value.parse().unwrap();
         self.emit_constant(value.into());
     }
 
     fn string(&mut self, _can_assign: bool) {
-        let s = self.gc.intern(self.previous.value.to_owned());
+        let s = self.gc.intern(unescape(self.previous.value));
         self.emit_constant(Value::String(s));
     }
 
-    fn named_variable(&mut self, name: &str, can_assign: bool) {
+    fn interpolation(&mut self, _can_assign: bool) {
+        let mut part_count: u16 = 0;
+
+        loop {
+            if !self.previous.value.is_empty() {
+                self.string(false);
+                part_count = part_count.saturating_add(1);
+            }
+
+            self.expression();
+            part_count = part_count.saturating_add(1);
+
+            if !self.matches(TokenType::Interpolation) {
+                break;
+            }
+        }
+
+        self.consume(
+            TokenType::String,
+            "Expect '}' after interpolated expression.",
+        );
+        if !self.previous.value.is_empty() {
+            self.string(false);
+            part_count = part_count.saturating_add(1);
+        }
+
+        if part_count == u16::MAX {
+            self.error("Too many interpolations in one string.");
+        }
+        self.emit_byte(OpCode::OpBuildString(part_count));
+    }
+
+    fn named_variable(&mut self, name: &'a str, can_assign: bool) {
+        let span = self.previous.span();
         let get_op;
         let set_op;
 
@@ -649,10 +1294,14 @@ impl<'a> Parser<'a> {
         };
 
         if can_assign && self.matches(TokenType::Equal) {
+            if let OpCode::OpSetGlobal(_) = set_op {
+                self.global_assignments.push((name, span));
+            }
+
             self.expression();
-            self.emit_byte(set_op);
+            self.emit_byte_at(set_op, span);
         } else {
-            self.emit_byte(get_op);
+            self.emit_byte_at(get_op, span);
         }
     }
 
@@ -663,13 +1312,13 @@ impl<'a> Parser<'a> {
 
     fn super_(&mut self, _can_assign: bool) {
         if self.current_class.is_none() {
-            self.error("Can't use 'super' outside of a class.");
+            self.semantic_error("Can't use 'super' outside of a class.");
         } else if self
             .current_class
             .as_ref()
-            .map_or(false, |cc| !cc.has_superclass)
+            .is_some_and(|cc| !cc.has_superclass)
         {
-            self.error("Can't use 'super' in a class with no superclass.");
+            self.semantic_error("Can't use 'super' in a class with no superclass.");
         }
 
         self.consume(TokenType::Dot, "Expect '.' after 'super'.");
@@ -688,7 +1337,7 @@ impl<'a> Parser<'a> {
 
     fn this(&mut self, _can_assign: bool) {
         if self.current_class.is_none() {
-            self.error("Can't use 'this' outside of a class.");
+            self.semantic_error("Can't use 'this' outside of a class.");
             return;
         }
 
@@ -697,12 +1346,13 @@ impl<'a> Parser<'a> {
 
     fn unary(&mut self, _can_assign: bool) {
         let op = self.previous.kind;
+        let span = self.previous.span();
 
         self.parse_precedence(Precedence::Unary);
 
         match op {
-            TokenType::Bang => self.emit_byte(OpCode::OpNot),
-            TokenType::Minus => self.emit_byte(OpCode::OpNegate),
+            TokenType::Bang => self.emit_byte_at(OpCode::OpNot, span),
+            TokenType::Minus => self.emit_byte_at(OpCode::OpNegate, span),
             _ => unreachable!(),
         };
     }
@@ -712,23 +1362,26 @@ impl<'a> Parser<'a> {
         use TokenType::*;
 
         let op = self.previous.kind;
+        let span = self.previous.span();
 
         let rule = ParseRule::get_rule(op);
         self.parse_precedence(rule.precedence + 1);
 
-        match op {
-            BangEqual => self.emit_bytes(OpEqual, OpNot),
-            EqualEqual => self.emit_byte(OpEqual),
-            Greater => self.emit_byte(OpGreater),
-            GreaterEqual => self.emit_bytes(OpLess, OpNot),
-            Less => self.emit_byte(OpLess),
-            LessEqual => self.emit_bytes(OpGreater, OpNot),
-            Plus => self.emit_byte(OpAdd),
-            Minus => self.emit_byte(OpSubtract),
-            Star => self.emit_byte(OpMultiply),
-            Slash => self.emit_byte(OpDivide),
+        let op_code = match op {
+            BangEqual | EqualEqual => OpEqual,
+            Greater | LessEqual => OpGreater,
+            Less | GreaterEqual => OpLess,
+            Plus => OpAdd,
+            Minus => OpSubtract,
+            Star => OpMultiply,
+            Slash => OpDivide,
             _ => unreachable!(),
         };
+
+        self.emit_byte_at(op_code, span);
+        if matches!(op, BangEqual | GreaterEqual | LessEqual) {
+            self.emit_byte_at(OpNot, span);
+        }
     }
 
     fn literal(&mut self, _can_assign: bool) {
@@ -741,22 +1394,79 @@ impl<'a> Parser<'a> {
     }
 
     fn call(&mut self, _can_assign: bool) {
+        let span = self.previous.span();
         let arg_count = self.argument_list();
-        self.emit_byte(OpCode::OpCall(arg_count));
+        self.emit_byte_at(OpCode::OpCall(arg_count), span);
     }
 
     fn dot(&mut self, can_assign: bool) {
         self.consume(TokenType::Identifier, "Expect property name after '.'.");
         let name = self.identifier_constant(self.previous.value);
+        let span = self.previous.span();
 
         if can_assign && self.matches(TokenType::Equal) {
             self.expression();
-            self.emit_byte(OpCode::OpSetProperty(name));
+            self.emit_byte_at(OpCode::OpSetProperty(name), span);
         } else if self.matches(TokenType::LeftParen) {
             let arg_count = self.argument_list();
-            self.emit_byte(OpCode::OpInvoke(name, arg_count));
+            self.emit_byte_at(OpCode::OpInvoke(name, arg_count), span);
         } else {
-            self.emit_byte(OpCode::OpGetProperty(name));
+            self.emit_byte_at(OpCode::OpGetProperty(name), span);
+        }
+    }
+
+    fn list(&mut self, _can_assign: bool) {
+        let mut item_count = 0;
+        if !self.check(TokenType::RightBracket) {
+            loop {
+                self.expression();
+                if item_count == u16::MAX {
+                    self.error("Can't have more than 65535 elements in a list literal.");
+                } else {
+                    item_count += 1;
+                }
+                if !self.matches(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightBracket, "Expect ']' after list elements.");
+        self.emit_byte(OpCode::OpBuildList(item_count));
+    }
+
+    fn map(&mut self, _can_assign: bool) {
+        let mut entry_count = 0;
+        if !self.check(TokenType::RightBrace) {
+            loop {
+                self.expression();
+                self.consume(TokenType::Colon, "Expect ':' after map key.");
+                self.expression();
+                if entry_count == u16::MAX {
+                    self.error("Can't have more than 65535 entries in a map literal.");
+                } else {
+                    entry_count += 1;
+                }
+                if !self.matches(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after map entries.");
+        self.emit_byte(OpCode::OpBuildMap(entry_count));
+    }
+
+    fn subscript(&mut self, can_assign: bool) {
+        let span = self.previous.span();
+        self.expression();
+        self.consume(TokenType::RightBracket, "Expect ']' after index.");
+
+        if can_assign && self.matches(TokenType::Equal) {
+            self.expression();
+            self.emit_byte_at(OpCode::OpSetIndex, span);
+        } else {
+            self.emit_byte_at(OpCode::OpGetIndex, span);
         }
     }
 
@@ -782,7 +1492,7 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn define_variable(&mut self, global: u8) {
+    fn define_variable(&mut self, global: u16) {
         if self.compiler.scope_depth > 0 {
             self.mark_initialized();
             return;
@@ -830,7 +1540,7 @@ impl<'a> Parser<'a> {
         self.patch_jump(end_jump);
     }
 
-    fn parse_variable(&mut self, message: &str) -> u8 {
+    fn parse_variable(&mut self, message: &str) -> u16 {
         self.consume(TokenType::Identifier, message);
 
         self.declare_variable();
@@ -850,48 +1560,53 @@ impl<'a> Parser<'a> {
         self.compiler.locals.last_mut().unwrap().depth = self.compiler.scope_depth;
     }
 
-    fn identifier_constant(&mut self, name: &str) -> u8 {
+    /// Exempts the last declared local, like a parameter, from the unused
+    /// variable warning.
+    fn mark_used(&mut self) {
+        if let Some(local) = self.compiler.locals.last_mut() {
+            local.is_used = true;
+        }
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> u16 {
         let identifier = self.gc.intern(name.to_owned());
         self.make_constant(Value::String(identifier))
     }
 
-    fn resolve_local(&mut self, name: &str) -> Option<u8> {
-        let result = self.compiler.resolve_local(name, &mut self.errors);
-        while let Some(e) = self.errors.pop() {
-            self.error(e);
-        }
-
-        result
+    fn resolve_local(&mut self, name: &str) -> Option<u16> {
+        self.compiler
+            .resolve_local(name, &self.previous, &mut self.diagnostics)
     }
 
-    fn resolve_upvalue(&mut self, name: &str) -> Option<u8> {
-        let result = self.compiler.resolve_upvalue(name, &mut self.errors);
-        while let Some(e) = self.errors.pop() {
-            self.error(e);
-        }
-
-        result
+    fn resolve_upvalue(&mut self, name: &str) -> Option<u16> {
+        self.compiler
+            .resolve_upvalue(name, &self.previous, &mut self.diagnostics)
     }
 
     fn add_local(&mut self, name: &'a str) {
-        if self.compiler.locals.len() == u8::MAX as usize + 1 {
+        if self.compiler.locals.len() == u16::MAX as usize + 1 {
             self.error("Too many local variables in function.");
             return;
         }
 
-        let local = Local::new(name, -1);
+        let local = Local::new(name, -1, self.previous.span());
         self.compiler.locals.push(local);
     }
 
     fn declare_variable(&mut self) {
+        let name = self.previous.value;
+
         if self.compiler.scope_depth == 0 {
+            self.global_definitions.insert(name);
             return;
         }
 
-        let name = self.previous.value;
-
         if self.compiler.is_local_declared(name) {
-            self.error("Already a variable with this name in this scope.");
+            self.semantic_error("Already a variable with this name in this scope.");
+        } else if !name.starts_with('_') && self.compiler.is_shadowing(name) {
+            let span = self.previous.span();
+            self.diagnostics
+                .warning(name, span, "Shadows a variable in an enclosing scope.");
         }
 
         self.add_local(name);
@@ -900,7 +1615,7 @@ impl<'a> Parser<'a> {
     fn synchronize(&mut self) {
         use TokenType::*;
 
-        self.panic_mode = false;
+        self.diagnostics.panic_mode = false;
 
         while self.current.kind != Eof {
             if self.previous.kind == Semicolon {
@@ -908,7 +1623,8 @@ impl<'a> Parser<'a> {
             }
 
             match self.current.kind {
-                Class | Fun | Var | For | If | While | Print | Return => return,
+                Class | Fun | Var | For | If | While | Print | Return | Throw | Try | Break
+                | Continue | Import | From => return,
                 _ => (),
             }
 
@@ -917,31 +1633,17 @@ impl<'a> Parser<'a> {
     }
 
     fn error_at_current(&mut self, message: &str) {
-        self.error_at(&self.current.clone(), message);
+        self.diagnostics.syntax_error(&self.current, message);
     }
 
     fn error(&mut self, message: &str) {
-        self.error_at(&self.previous.clone(), message);
+        self.diagnostics.syntax_error(&self.previous, message);
     }
 
-    fn error_at(&mut self, token: &Token<'a>, message: &str) {
-        if self.panic_mode {
-            return;
-        }
-
-        self.panic_mode = true;
-
-        eprint!("[line {}] Error", token.line);
-
-        match token.kind {
-            TokenType::Eof => eprint!(" at end"),
-            TokenType::Error => (),
-            TokenType::String => eprint!(" at '\"{}\"'", token.value),
-            _ => eprint!(" at '{}'", token.value),
-        }
-
-        eprintln!(": {}", message);
-        self.had_error = true;
+    /// Reports an error at the previous token that doesn't throw the parser
+    /// off, so errors in the rest of the statement are reported too.
+    fn semantic_error(&mut self, message: &str) {
+        self.diagnostics.error(&self.previous, message);
     }
 }
 
@@ -995,14 +1697,22 @@ impl<'a> ParseRule<'a> {
                 Self::new(Some(Parser::grouping), Some(Parser::call), Precedence::Call)
             }
             TokenType::RightParen => Self::new(None, None, Precedence::None),
-            TokenType::LeftBrace => Self::new(None, None, Precedence::None),
+            TokenType::LeftBrace => Self::new(Some(Parser::map), None, Precedence::None),
             TokenType::RightBrace => Self::new(None, None, Precedence::None),
+            TokenType::LeftBracket => Self::new(
+                Some(Parser::list),
+                Some(Parser::subscript),
+                Precedence::Call,
+            ),
+            TokenType::RightBracket => Self::new(None, None, Precedence::None),
             TokenType::Comma => Self::new(None, None, Precedence::None),
+            TokenType::Continue => Self::new(None, None, Precedence::None),
             TokenType::Dot => Self::new(None, Some(Parser::dot), Precedence::Call),
             TokenType::Minus => {
                 Self::new(Some(Parser::unary), Some(Parser::binary), Precedence::Term)
             }
             TokenType::Plus => Self::new(None, Some(Parser::binary), Precedence::Term),
+            TokenType::Colon => Self::new(None, None, Precedence::None),
             TokenType::Semicolon => Self::new(None, None, Precedence::None),
             TokenType::Slash => Self::new(None, Some(Parser::binary), Precedence::Factor),
             TokenType::Star => Self::new(None, Some(Parser::binary), Precedence::Factor),
@@ -1016,23 +1726,34 @@ impl<'a> ParseRule<'a> {
             }
             TokenType::Less => Self::new(None, Some(Parser::binary), Precedence::Comparison),
             TokenType::LessEqual => Self::new(None, Some(Parser::binary), Precedence::Comparison),
+            TokenType::Arrow => Self::new(None, None, Precedence::None),
             TokenType::Identifier => Self::new(Some(Parser::variable), None, Precedence::None),
             TokenType::String => Self::new(Some(Parser::string), None, Precedence::None),
+            TokenType::Interpolation => {
+                Self::new(Some(Parser::interpolation), None, Precedence::None)
+            }
             TokenType::Number => Self::new(Some(Parser::number), None, Precedence::None),
             TokenType::And => Self::new(None, Some(Parser::and), Precedence::And),
+            TokenType::Break => Self::new(None, None, Precedence::None),
+            TokenType::Catch => Self::new(None, None, Precedence::None),
             TokenType::Class => Self::new(None, None, Precedence::None),
             TokenType::Else => Self::new(None, None, Precedence::None),
             TokenType::False => Self::new(Some(Parser::literal), None, Precedence::None),
+            TokenType::Finally => Self::new(None, None, Precedence::None),
             TokenType::For => Self::new(None, None, Precedence::None),
-            TokenType::Fun => Self::new(None, None, Precedence::None),
+            TokenType::From => Self::new(None, None, Precedence::None),
+            TokenType::Fun => Self::new(Some(Parser::lambda), None, Precedence::None),
             TokenType::If => Self::new(None, None, Precedence::None),
+            TokenType::Import => Self::new(None, None, Precedence::None),
             TokenType::Nil => Self::new(Some(Parser::literal), None, Precedence::None),
             TokenType::Or => Self::new(None, Some(Parser::or), Precedence::Or),
             TokenType::Print => Self::new(None, None, Precedence::None),
             TokenType::Return => Self::new(None, None, Precedence::None),
             TokenType::Super => Self::new(Some(Parser::super_), None, Precedence::None),
             TokenType::This => Self::new(Some(Parser::this), None, Precedence::None),
+            TokenType::Throw => Self::new(None, None, Precedence::None),
             TokenType::True => Self::new(Some(Parser::literal), None, Precedence::None),
+            TokenType::Try => Self::new(None, None, Precedence::None),
             TokenType::Var => Self::new(None, None, Precedence::None),
             TokenType::While => Self::new(None, None, Precedence::None),
             TokenType::Error => Self::new(None, None, Precedence::None),
@@ -1041,7 +1762,22 @@ impl<'a> ParseRule<'a> {
     }
 }
 
-pub fn compile(source: &str, gc: &mut Gc) -> Option<GcRef<Function>> {
-    let parser = Parser::new(source, gc);
+/// The compiled script, or the errors that prevented compiling it, along with
+/// any warnings.
+pub type Compilation = (
+    Result<GcRef<Function>, Vec<CompileError>>,
+    Vec<CompileWarning>,
+);
+
+/// Compiles a script, or the top level of `module` if it is given. `globals`
+/// are the globals already defined for the script, which assignments in it
+/// are checked against.
+pub fn compile(
+    source: &str,
+    gc: &mut Gc,
+    globals: &Table,
+    module: Option<GcRef<Module>>,
+) -> Compilation {
+    let parser = Parser::new(source, gc, globals, module);
     parser.compile()
 }
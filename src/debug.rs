@@ -1,3 +1,5 @@
+use std::io::{Result, Write};
+
 use crate::chunk::{Chunk, OpCode};
 use crate::gc::{Gc, GcRef, GcTraceFormatter};
 use crate::value::Value;
@@ -5,31 +7,35 @@ use crate::value::Value;
 pub struct Disassembler<'a> {
     gc: &'a Gc,
     chunk: &'a Chunk,
+    out: &'a mut dyn Write,
 }
 
 impl<'a> Disassembler<'a> {
-    pub fn new(gc: &'a Gc, chunk: &'a Chunk) -> Self {
-        Self { gc, chunk }
+    pub fn new(gc: &'a Gc, chunk: &'a Chunk, out: &'a mut dyn Write) -> Self {
+        Self { gc, chunk, out }
     }
 
-    pub fn disassemble_chunk(&self, name: GcRef<String>) {
-        println!("== {} ==", self.gc.deref(name));
+    pub fn disassemble_chunk(&mut self, name: GcRef<String>) -> Result<()> {
+        writeln!(self.out, "== {} ==", self.gc.deref(name))?;
 
         let mut offset = 0;
         while offset < self.chunk.code.len() {
-            offset = self.disassemble_instruction(offset);
+            offset = self.disassemble_instruction(offset)?;
         }
+
+        writeln!(self.out)
     }
 
-    pub fn disassemble_instruction(&self, offset: usize) -> usize {
+    pub fn disassemble_instruction(&mut self, offset: usize) -> Result<usize> {
         use OpCode::*;
 
-        print!("{:04} ", offset);
+        write!(self.out, "{:04} ", offset)?;
 
-        if offset > 0 && self.chunk.lines[offset] == self.chunk.lines[offset - 1] {
-            print!("   | ");
+        let line = self.chunk.spans[offset].line;
+        if offset > 0 && line == self.chunk.spans[offset - 1].line {
+            write!(self.out, "   | ")?;
         } else {
-            print!("{:4} ", self.chunk.lines[offset]);
+            write!(self.out, "{:4} ", line)?;
         }
 
         let instruction = self.chunk.code[offset];
@@ -62,72 +68,98 @@ impl<'a> Disassembler<'a> {
             OpJump(jump) => self.jump_instruction("OP_JUMP", 1, offset, jump),
             OpJumpIfFalse(jump) => self.jump_instruction("OP_JUMP_IF_FALSE", 1, offset, jump),
             OpLoop(jump) => self.jump_instruction("OP_LOOP", -1, offset, jump),
-            OpCall(slot) => self.byte_instruction("OP_CALL", slot),
+            OpCall(slot) => self.byte_instruction("OP_CALL", slot.into()),
             OpInvoke(c, args) => self.invoke_instruction("OP_INVOKE", c, args),
             OpSuperInvoke(c, args) => self.invoke_instruction("OP_SUPER_INVOKE", c, args),
             OpClosure(constant) => {
                 let value = self.chunk.constants[constant as usize];
-                println!(
+                writeln!(
+                    self.out,
                     "{:<16} {:4} {}",
                     "OP_CLOSURE",
                     constant,
                     GcTraceFormatter::new(value, self.gc)
-                );
+                )?;
 
                 if let Value::Closure(closure) = value {
                     let closure = self.gc.deref(closure);
                     let function = self.gc.deref(closure.function);
                     for upvalue in &function.upvalues {
                         let is_local = if upvalue.is_local { "local" } else { "upvalue" };
-                        println!("{:04}      | {:>20}{} {}", "", " ", is_local, upvalue.index);
+                        writeln!(
+                            self.out,
+                            "{:04}      | {:>20}{} {}",
+                            "", " ", is_local, upvalue.index
+                        )?;
                     }
                 }
+
+                Ok(())
             }
             OpCloseUpvalue => self.simple_instruction("OP_CLOSE_UPVALUE"),
             OpReturn => self.simple_instruction("OP_RETURN"),
             OpClass(c) => self.constant_instruction("OP_CLASS", c),
             OpInherit => self.simple_instruction("OP_INHERIT"),
             OpMethod(c) => self.constant_instruction("OP_METHOD", c),
-        }
+            OpStaticMethod(c) => self.constant_instruction("OP_STATIC_METHOD", c),
+            OpGetter(c) => self.constant_instruction("OP_GETTER", c),
+            OpSetter(c) => self.constant_instruction("OP_SETTER", c),
+            OpBuildList(count) => self.byte_instruction("OP_BUILD_LIST", count),
+            OpBuildMap(count) => self.byte_instruction("OP_BUILD_MAP", count),
+            OpBuildString(count) => self.byte_instruction("OP_BUILD_STRING", count),
+            OpGetIndex => self.simple_instruction("OP_GET_INDEX"),
+            OpSetIndex => self.simple_instruction("OP_SET_INDEX"),
+            OpThrow => self.simple_instruction("OP_THROW"),
+            OpImport(c) => self.constant_instruction("OP_IMPORT", c),
+        }?;
 
-        offset + 1
+        Ok(offset + 1)
     }
 
-    fn simple_instruction(&self, name: &str) {
-        println!("{}", name);
+    fn simple_instruction(&mut self, name: &str) -> Result<()> {
+        writeln!(self.out, "{}", name)
     }
 
-    fn constant_instruction(&self, name: &str, constant: u8) {
+    fn constant_instruction(&mut self, name: &str, constant: u16) -> Result<()> {
         let value = self.chunk.constants[constant as usize];
-        println!(
+        writeln!(
+            self.out,
             "{:<16} {:4} '{}'",
             name,
             constant,
             GcTraceFormatter::new(value, self.gc)
-        );
+        )
     }
 
-    fn invoke_instruction(&self, name: &str, constant: u8, arg_count: u8) {
+    fn invoke_instruction(&mut self, name: &str, constant: u16, arg_count: u8) -> Result<()> {
         let value = self.chunk.constants[constant as usize];
-        println!(
+        writeln!(
+            self.out,
             "{:<16} ({} args) {:4} '{}'",
             name,
             arg_count,
             constant,
             GcTraceFormatter::new(value, self.gc)
-        );
+        )
     }
 
-    fn byte_instruction(&self, name: &str, slot: u8) {
-        println!("{:<16} {:4}", name, slot);
+    fn byte_instruction(&mut self, name: &str, slot: u16) -> Result<()> {
+        writeln!(self.out, "{:<16} {:4}", name, slot)
     }
 
-    fn jump_instruction(&self, name: &str, sign: isize, offset: usize, jump: u16) {
-        println!(
+    fn jump_instruction(
+        &mut self,
+        name: &str,
+        sign: isize,
+        offset: usize,
+        jump: u16,
+    ) -> Result<()> {
+        writeln!(
+            self.out,
             "{:<16} {:4} -> {}",
             name,
             offset,
             offset as isize + 1 + sign * jump as isize
-        );
+        )
     }
 }
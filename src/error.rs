@@ -0,0 +1,276 @@
+use crate::chunk::Span;
+use crate::value::Value;
+
+use std::fmt;
+use std::rc::Rc;
+
+/// The line of source an error points at. Errors formatted with `{:#}` show
+/// it under the message, with the offending part underlined.
+#[derive(Clone, Debug)]
+pub struct Excerpt {
+    pub line: u32,
+    pub text: String,
+    /// The range of characters in `text` to underline.
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Excerpt {
+    pub(crate) fn new(source: &str, span: Span) -> Option<Self> {
+        if span.offset > source.len() || !source.is_char_boundary(span.offset) {
+            return None;
+        }
+
+        let line_start = source[..span.offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[span.offset..]
+            .find('\n')
+            .map_or(source.len(), |i| span.offset + i);
+        let span_end = (span.offset + span.length).min(line_end);
+
+        let start = source[line_start..span.offset].chars().count();
+        let end = start + source.get(span.offset..span_end)?.chars().count();
+
+        Some(Self {
+            line: source[..span.offset].matches('\n').count() as u32 + 1,
+            text: source[line_start..line_end]
+                .trim_end_matches('\r')
+                .to_owned(),
+            start,
+            end,
+        })
+    }
+}
+
+/// A span together with the source it's in, kept so an excerpt can be
+/// worked out only if it's needed.
+#[derive(Clone)]
+pub(crate) struct SourceSpan {
+    pub source: Rc<str>,
+    pub span: Span,
+}
+
+impl fmt::Debug for SourceSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.span.fmt(f)
+    }
+}
+
+impl fmt::Display for Excerpt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let gutter = self.line.to_string().len();
+        let padding: String = self
+            .text
+            .chars()
+            .take(self.start)
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect();
+        let underline = "^".repeat((self.end - self.start).max(1));
+
+        writeln!(f, "{:gutter$} |", "")?;
+        writeln!(f, "{} | {}", self.line, self.text)?;
+        write!(f, "{:gutter$} | {}{}", "", padding, underline)
+    }
+}
+
+/// An error found while compiling a script.
+#[derive(Clone, Debug)]
+pub struct CompileError {
+    pub line: u32,
+    pub column: u32,
+    /// The token the error was reported at. `None` for errors produced by the
+    /// scanner itself, an empty string for the end of the input.
+    pub lexeme: Option<String>,
+    pub message: String,
+    pub excerpt: Option<Excerpt>,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_diagnostic(
+            f,
+            "Error",
+            self.line,
+            &self.lexeme,
+            &self.message,
+            &self.excerpt,
+        )
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// Something suspicious the compiler found that doesn't stop the script from
+/// running, like an unused local variable.
+#[derive(Clone, Debug)]
+pub struct CompileWarning {
+    pub line: u32,
+    pub column: u32,
+    pub lexeme: Option<String>,
+    pub message: String,
+    pub excerpt: Option<Excerpt>,
+}
+
+impl fmt::Display for CompileWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_diagnostic(
+            f,
+            "Warning",
+            self.line,
+            &self.lexeme,
+            &self.message,
+            &self.excerpt,
+        )
+    }
+}
+
+fn write_diagnostic(
+    f: &mut fmt::Formatter<'_>,
+    label: &str,
+    line: u32,
+    lexeme: &Option<String>,
+    message: &str,
+    excerpt: &Option<Excerpt>,
+) -> fmt::Result {
+    write!(f, "[line {}] {}", line, label)?;
+
+    match lexeme.as_deref() {
+        None => (),
+        Some("") => write!(f, " at end")?,
+        Some(lexeme) => write!(f, " at '{}'", lexeme)?,
+    }
+
+    write!(f, ": {}", message)?;
+
+    if let Some(excerpt) = excerpt.as_ref().filter(|_| f.alternate()) {
+        write!(f, "\n{}", excerpt)?;
+    }
+
+    Ok(())
+}
+
+/// A call frame that was active when a runtime error happened.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub function: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] in {}", self.line, self.function)
+    }
+}
+
+/// An error raised while running a script, with the call stack innermost
+/// frame first.
+#[derive(Clone, Debug)]
+pub struct RuntimeError {
+    pub message: String,
+    pub stack: Vec<Frame>,
+    /// Where in the innermost frame the error was raised.
+    pub(crate) location: Option<SourceSpan>,
+    /// The value passed to `throw`, handed to the `catch` block instead of a
+    /// new error instance if the error is caught.
+    pub(crate) thrown: Option<Value>,
+    /// Set when the script called `exit`, which unwinds every frame without
+    /// running `catch` blocks.
+    pub exit_code: Option<i32>,
+}
+
+impl RuntimeError {
+    /// How many frames are shown at each end of a longer stack trace.
+    const TRACE_EDGE: usize = 10;
+
+    /// The line of source the error was raised at. It's only read from the
+    /// source when asked for, since errors caught by a script never need it.
+    pub fn excerpt(&self) -> Option<Excerpt> {
+        let location = self.location.as_ref()?;
+        Excerpt::new(&location.source, location.span)
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+
+        if let Some(excerpt) = self.excerpt().filter(|_| f.alternate()) {
+            write!(f, "\n{}", excerpt)?;
+        }
+
+        let hidden = self.stack.len().saturating_sub(2 * Self::TRACE_EDGE + 1);
+        for (i, frame) in self.stack.iter().enumerate() {
+            if hidden == 0 || !(Self::TRACE_EDGE..Self::TRACE_EDGE + hidden).contains(&i) {
+                write!(f, "\n{}", frame)?;
+            } else if i == Self::TRACE_EDGE {
+                write!(f, "\n... {} more", hidden)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// Why compiled bytecode couldn't be loaded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BytecodeError {
+    /// The data doesn't start with the magic number of compiled Lox files.
+    NotBytecode,
+    /// The file was written for another version of the bytecode format.
+    UnsupportedVersion(u16),
+    /// The checksum doesn't match the contents, so the file is corrupted.
+    ChecksumMismatch,
+    /// The file ends in the middle of a function.
+    Truncated,
+    /// The file is well-formed but its code would crash the VM.
+    Invalid(String),
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BytecodeError::NotBytecode => write!(f, "Not a compiled Lox file."),
+            BytecodeError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported bytecode version {}.", version)
+            }
+            BytecodeError::ChecksumMismatch => write!(f, "Bytecode checksum mismatch."),
+            BytecodeError::Truncated => write!(f, "Unexpected end of bytecode."),
+            BytecodeError::Invalid(message) => write!(f, "Invalid bytecode: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {}
+
+#[derive(Clone, Debug)]
+pub enum InterpretError {
+    Compile(Vec<CompileError>),
+    Bytecode(BytecodeError),
+    Runtime(RuntimeError),
+    /// The script called `exit` with this code.
+    Exit(i32),
+}
+
+impl fmt::Display for InterpretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpretError::Compile(errors) => {
+                for (i, error) in errors.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    fmt::Display::fmt(error, f)?;
+                }
+
+                Ok(())
+            }
+            InterpretError::Bytecode(error) => fmt::Display::fmt(error, f),
+            InterpretError::Runtime(error) => fmt::Display::fmt(error, f),
+            InterpretError::Exit(code) => write!(f, "Exited with code {}.", code),
+        }
+    }
+}
+
+impl std::error::Error for InterpretError {}
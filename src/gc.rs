@@ -1,10 +1,14 @@
 // GC implementation taken from https://github.com/ceronman/loxido
 
 use std::any::{type_name, Any};
+use std::cell::RefCell;
 use std::collections::{HashMap, VecDeque};
 use std::marker::PhantomData;
 use std::{fmt, hash, mem};
 
+#[cfg(feature = "debug_log_gc")]
+use std::{io, io::Write, rc::Rc};
+
 use crate::table::Table;
 use crate::value::Value;
 
@@ -61,7 +65,7 @@ impl<T: GcTrace> PartialEq for GcRef<T> {
     }
 }
 
-impl hash::Hash for GcRef<String> {
+impl<T: GcTrace> hash::Hash for GcRef<T> {
     fn hash<H: hash::Hasher>(&self, state: &mut H) {
         self.index.hash(state)
     }
@@ -80,12 +84,20 @@ pub struct Gc {
     objects: Vec<Option<GcObjectHeader>>,
     strings: HashMap<String, GcRef<String>>,
     grey_stack: VecDeque<usize>,
+    /// The object being traced, which is taken out of `objects` meanwhile but
+    /// can refer to itself.
+    blackening: Option<usize>,
+    /// The objects being formatted, so one that contains itself is written
+    /// once instead of forever.
+    formatting: RefCell<Vec<usize>>,
+    #[cfg(feature = "debug_log_gc")]
+    log: Rc<RefCell<dyn Write>>,
 }
 
 impl Gc {
     const HEAP_GROW_FACTOR: usize = 2;
 
-    pub fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Gc {
             bytes_allocated: 0,
             next_gc: 1024 * 1024,
@@ -93,10 +105,29 @@ impl Gc {
             objects: Vec::new(),
             strings: HashMap::new(),
             grey_stack: VecDeque::new(),
+            blackening: None,
+            formatting: RefCell::new(Vec::new()),
+            #[cfg(feature = "debug_log_gc")]
+            log: Rc::new(RefCell::new(io::stdout())),
         }
     }
 
-    pub fn alloc<T: GcTrace + 'static + fmt::Debug>(&mut self, object: T) -> GcRef<T> {
+    #[cfg(feature = "debug_log_gc")]
+    pub(crate) fn set_log(&mut self, log: Rc<RefCell<dyn Write>>) {
+        self.log = log;
+    }
+
+    /// A collector for unit tests that doesn't log allocations to stdout.
+    #[cfg(test)]
+    pub(crate) fn quiet() -> Self {
+        #[allow(unused_mut)]
+        let mut gc = Gc::new();
+        #[cfg(feature = "debug_log_gc")]
+        gc.set_log(Rc::new(RefCell::new(io::sink())));
+        gc
+    }
+
+    pub(crate) fn alloc<T: GcTrace + 'static + fmt::Debug>(&mut self, object: T) -> GcRef<T> {
         #[cfg(feature = "debug_log_gc")]
         let repr = format!("{:?}", object).chars().take(32).collect::<String>();
         let size = object.size() + mem::size_of::<GcObjectHeader>();
@@ -117,7 +148,8 @@ impl Gc {
             }
         };
         #[cfg(feature = "debug_log_gc")]
-        println!(
+        let _ = writeln!(
+            self.log.borrow_mut(),
             "alloc(id:{}, type:{}: repr: {}, b:{}, t:{})",
             index,
             type_name::<T>(),
@@ -131,7 +163,7 @@ impl Gc {
         }
     }
 
-    pub fn intern(&mut self, name: String) -> GcRef<String> {
+    pub(crate) fn intern(&mut self, name: String) -> GcRef<String> {
         if let Some(&value) = self.strings.get(&name) {
             value
         } else {
@@ -141,6 +173,28 @@ impl Gc {
         }
     }
 
+    pub fn find_string(&self, name: &str) -> Option<GcRef<String>> {
+        self.strings.get(name).copied()
+    }
+
+    /// Formats the object behind `reference`, or writes `cycle` if it's
+    /// already being formatted further up.
+    pub(crate) fn format_once<T: GcTrace + 'static>(
+        &self,
+        reference: GcRef<T>,
+        f: &mut fmt::Formatter,
+        cycle: &str,
+    ) -> fmt::Result {
+        if self.formatting.borrow().contains(&reference.index) {
+            return write!(f, "{}", cycle);
+        }
+
+        self.formatting.borrow_mut().push(reference.index);
+        let result = self.deref(reference).format(f, self);
+        self.formatting.borrow_mut().pop();
+        result
+    }
+
     pub fn deref<T: GcTrace + 'static>(&self, reference: GcRef<T>) -> &T {
         self.objects[reference.index]
             .as_ref()
@@ -151,7 +205,7 @@ impl Gc {
             .unwrap_or_else(|| panic!("Reference {} not found", reference.index))
     }
 
-    pub fn deref_mut<T: GcTrace + 'static>(&mut self, reference: GcRef<T>) -> &mut T {
+    pub(crate) fn deref_mut<T: GcTrace + 'static>(&mut self, reference: GcRef<T>) -> &mut T {
         self.objects[reference.index]
             .as_mut()
             .unwrap()
@@ -163,7 +217,7 @@ impl Gc {
 
     fn free(&mut self, index: usize) {
         #[cfg(feature = "debug_log_gc")]
-        println!("free (id:{})", index,);
+        let _ = writeln!(self.log.borrow_mut(), "free (id:{})", index);
         if let Some(old) = self.objects[index].take() {
             self.bytes_allocated -= old.size;
             self.free_slots.push(index)
@@ -172,7 +226,7 @@ impl Gc {
         }
     }
 
-    pub fn collect_garbage(&mut self) {
+    pub(crate) fn collect_garbage(&mut self) {
         #[cfg(feature = "debug_log_gc")]
         let before = self.bytes_allocated;
 
@@ -182,7 +236,8 @@ impl Gc {
         self.next_gc = self.bytes_allocated * Gc::HEAP_GROW_FACTOR;
 
         #[cfg(feature = "debug_log_gc")]
-        println!(
+        let _ = writeln!(
+            self.log.borrow_mut(),
             "collected {} bytes (from {} to {}) next at {}\n",
             before - self.bytes_allocated,
             before,
@@ -199,25 +254,28 @@ impl Gc {
 
     fn blacken_object(&mut self, index: usize) {
         #[cfg(feature = "debug_log_gc")]
-        println!("blacken(id:{})", index);
+        let _ = writeln!(self.log.borrow_mut(), "blacken(id:{})", index);
 
         let object = self.objects[index].take();
+        self.blackening = Some(index);
         object.as_ref().unwrap().obj.trace(self);
+        self.blackening = None;
         self.objects[index] = object;
     }
 
-    pub fn mark_value(&mut self, value: Value) {
+    pub(crate) fn mark_value(&mut self, value: Value) {
         value.trace(self);
     }
 
-    pub fn mark_object<T: GcTrace>(&mut self, obj: GcRef<T>) {
+    pub(crate) fn mark_object<T: GcTrace>(&mut self, obj: GcRef<T>) {
         if let Some(object) = self.objects[obj.index].as_mut() {
             if object.is_marked {
                 return;
             }
 
             #[cfg(feature = "debug_log_gc")]
-            println!(
+            let _ = writeln!(
+                self.log.borrow_mut(),
                 "mark(id:{}, type:{}, val:{:?})",
                 obj.index,
                 type_name::<T>(),
@@ -225,12 +283,12 @@ impl Gc {
             );
             object.is_marked = true;
             self.grey_stack.push_back(obj.index);
-        } else {
+        } else if self.blackening != Some(obj.index) {
             panic!("Marking already disposed object {}", obj.index)
         }
     }
 
-    pub fn mark_table(&mut self, table: &Table) {
+    pub(crate) fn mark_table(&mut self, table: &Table) {
         for (&k, &v) in table {
             self.mark_object(k);
             self.mark_value(v);
@@ -238,12 +296,12 @@ impl Gc {
     }
 
     #[cfg(feature = "debug_stress_gc")]
-    pub fn should_gc(&self) -> bool {
+    pub(crate) fn should_gc(&self) -> bool {
         true
     }
 
     #[cfg(not(feature = "debug_stress_gc"))]
-    pub fn should_gc(&self) -> bool {
+    pub(crate) fn should_gc(&self) -> bool {
         self.bytes_allocated > self.next_gc
     }
 
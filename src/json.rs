@@ -0,0 +1,532 @@
+use std::fmt::{self, Write};
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::gc::{Gc, GcTraceFormatter};
+use crate::value::Value;
+
+/// How deeply arrays and objects can nest, so deep input can't overflow the
+/// stack while it's parsed or written.
+const MAX_DEPTH: usize = 512;
+
+/// The most spaces a level is indented by, like `JSON.stringify` in
+/// JavaScript. Together with `MAX_DEPTH` this keeps indentation small.
+const MAX_INDENT: usize = 10;
+
+/// A parsed JSON value, before it's turned into Lox values.
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+/// Malformed JSON, with where in the input it was found.
+#[derive(Debug)]
+pub struct JsonError {
+    pub message: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid JSON at line {}, column {}: {}.",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+pub fn parse(source: &str) -> Result<Json, JsonError> {
+    let mut parser = Parser {
+        chars: source.chars().peekable(),
+        line: 1,
+        column: 1,
+    };
+
+    parser.skip_whitespace();
+    let json = parser.value(0)?;
+    parser.skip_whitespace();
+
+    match parser.chars.peek() {
+        Some(_) => Err(parser.expected("the end of the input")),
+        None => Ok(json),
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: u32,
+    column: u32,
+}
+
+impl<'a> Parser<'a> {
+    fn advance(&mut self) -> Option<char> {
+        let ch = self.chars.next()?;
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        Some(ch)
+    }
+
+    fn matches(&mut self, expected: char) -> bool {
+        let matches = self.chars.peek() == Some(&expected);
+        if matches {
+            self.advance();
+        }
+
+        matches
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.chars.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.advance();
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> JsonError {
+        JsonError {
+            message: message.into(),
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn expected(&mut self, what: &str) -> JsonError {
+        match self.chars.peek().copied() {
+            Some(ch) => self.error(format!("Expected {} but found '{}'", what, ch)),
+            None => self.error(format!("Expected {} but found the end of the input", what)),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, JsonError> {
+        match self.chars.peek() {
+            Some('n') => self.literal("null", Json::Null),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('-' | '0'..='9') => self.number(),
+            Some('[') => self.array(depth),
+            Some('{') => self.object(depth),
+            _ => Err(self.expected("a value")),
+        }
+    }
+
+    fn literal(&mut self, word: &str, json: Json) -> Result<Json, JsonError> {
+        for expected in word.chars() {
+            if !self.matches(expected) {
+                return Err(self.expected(&format!("'{}'", word)));
+            }
+        }
+
+        Ok(json)
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Json, JsonError> {
+        if depth == MAX_DEPTH {
+            return Err(self.error("Too deeply nested"));
+        }
+
+        self.advance();
+        self.skip_whitespace();
+
+        let mut items = Vec::new();
+        if self.matches(']') {
+            return Ok(Json::Array(items));
+        }
+
+        loop {
+            self.skip_whitespace();
+            items.push(self.value(depth + 1)?);
+            self.skip_whitespace();
+
+            if self.matches(']') {
+                return Ok(Json::Array(items));
+            } else if !self.matches(',') {
+                return Err(self.expected("',' or ']'"));
+            }
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Json, JsonError> {
+        if depth == MAX_DEPTH {
+            return Err(self.error("Too deeply nested"));
+        }
+
+        self.advance();
+        self.skip_whitespace();
+
+        let mut entries = Vec::new();
+        if self.matches('}') {
+            return Ok(Json::Object(entries));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.chars.peek() != Some(&'"') {
+                return Err(self.expected("a string key"));
+            }
+            let key = self.string()?;
+
+            self.skip_whitespace();
+            if !self.matches(':') {
+                return Err(self.expected("':'"));
+            }
+
+            self.skip_whitespace();
+            entries.push((key, self.value(depth + 1)?));
+            self.skip_whitespace();
+
+            if self.matches('}') {
+                return Ok(Json::Object(entries));
+            } else if !self.matches(',') {
+                return Err(self.expected("',' or '}'"));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.advance();
+
+        let mut string = String::new();
+        loop {
+            match self.chars.peek().copied() {
+                None => return Err(self.error("Unterminated string")),
+                Some('"') => {
+                    self.advance();
+                    return Ok(string);
+                }
+                Some('\\') => {
+                    self.advance();
+                    string.push(self.escape()?);
+                }
+                Some(ch) if ch < ' ' => {
+                    return Err(self.error("Unescaped control character in string"));
+                }
+                Some(ch) => {
+                    self.advance();
+                    string.push(ch);
+                }
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, JsonError> {
+        let ch = match self.chars.peek() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                self.advance();
+                return self.unicode_escape();
+            }
+            _ => return Err(self.error("Invalid escape sequence")),
+        };
+
+        self.advance();
+        Ok(ch)
+    }
+
+    /// Reads the digits of a `\u` escape, and of the low surrogate escape that
+    /// must follow a high surrogate.
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let mut code = self.hex_digits()?;
+        if (0xD800..0xDC00).contains(&code) {
+            if !(self.matches('\\') && self.matches('u')) {
+                return Err(self.error("Expected a low surrogate escape"));
+            }
+
+            let low = self.hex_digits()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("Invalid low surrogate"));
+            }
+            code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+        }
+
+        char::from_u32(code).ok_or_else(|| self.error("Invalid Unicode escape"))
+    }
+
+    fn hex_digits(&mut self) -> Result<u32, JsonError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.chars.peek().and_then(|ch| ch.to_digit(16)) {
+                Some(digit) => code = code * 16 + digit,
+                None => return Err(self.error("Invalid Unicode escape")),
+            }
+            self.advance();
+        }
+
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<Json, JsonError> {
+        let mut text = String::new();
+        if self.matches('-') {
+            text.push('-');
+        }
+
+        if self.matches('0') {
+            text.push('0');
+        } else {
+            self.digits(&mut text)?;
+        }
+
+        if self.matches('.') {
+            text.push('.');
+            self.digits(&mut text)?;
+        }
+
+        if let Some(&exponent @ ('e' | 'E')) = self.chars.peek() {
+            self.advance();
+            text.push(exponent);
+            if let Some(&sign @ ('+' | '-')) = self.chars.peek() {
+                self.advance();
+                text.push(sign);
+            }
+            self.digits(&mut text)?;
+        }
+
+        text.parse()
+            .map(Json::Number)
+            .map_err(|_| self.error("Invalid number"))
+    }
+
+    fn digits(&mut self, text: &mut String) -> Result<(), JsonError> {
+        if !self.chars.peek().is_some_and(char::is_ascii_digit) {
+            return Err(self.expected("a digit"));
+        }
+
+        while let Some(&digit) = self.chars.peek().filter(|ch| ch.is_ascii_digit()) {
+            self.advance();
+            text.push(digit);
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a Lox value as JSON, pretty-printed with `indent` spaces per level
+/// if it's given, up to `MAX_INDENT`. Only nil, booleans, finite numbers,
+/// strings, lists and maps with string keys can be written.
+pub fn stringify(value: Value, gc: &Gc, indent: Option<usize>) -> Result<String, String> {
+    let mut writer = Writer {
+        gc,
+        indent: indent.map(|indent| indent.min(MAX_INDENT)),
+        output: String::new(),
+        seen: Vec::new(),
+    };
+
+    writer.value(value)?;
+    Ok(writer.output)
+}
+
+struct Writer<'gc> {
+    gc: &'gc Gc,
+    indent: Option<usize>,
+    output: String,
+    /// The lists and maps being written, innermost last.
+    seen: Vec<Value>,
+}
+
+impl<'gc> Writer<'gc> {
+    fn value(&mut self, value: Value) -> Result<(), String> {
+        let gc = self.gc;
+
+        match value {
+            Value::Nil => self.output += "null",
+            Value::Bool(value) => self.output += if value { "true" } else { "false" },
+            Value::Number(value) if value.is_finite() => {
+                let _ = write!(self.output, "{}", value);
+            }
+            Value::Number(_) => return Err("Can't convert NaN or infinity to JSON.".to_owned()),
+            Value::String(string) => self.string(gc.deref::<String>(string)),
+            Value::List(list) => {
+                let items = &gc.deref(list).items;
+                self.begin(value, '[')?;
+                for (i, &item) in items.iter().enumerate() {
+                    if i > 0 {
+                        self.output.push(',');
+                    }
+                    self.newline();
+                    self.value(item)?;
+                }
+                self.end(']', items.is_empty());
+            }
+            Value::Map(map) => {
+                let entries = &gc.deref(map).entries;
+                self.begin(value, '{')?;
+                for (i, &(key, item)) in entries.iter().enumerate() {
+                    let Value::String(key) = key else {
+                        return Err("JSON object keys must be strings.".to_owned());
+                    };
+
+                    if i > 0 {
+                        self.output.push(',');
+                    }
+                    self.newline();
+                    self.string(gc.deref::<String>(key));
+                    self.output.push(':');
+                    if self.indent.is_some() {
+                        self.output.push(' ');
+                    }
+                    self.value(item)?;
+                }
+                self.end('}', entries.is_empty());
+            }
+            _ => {
+                return Err(format!(
+                    "Can't convert {} to JSON.",
+                    GcTraceFormatter::new(value, gc)
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts writing a list or map, unless it's already being written.
+    fn begin(&mut self, value: Value, open: char) -> Result<(), String> {
+        if self.seen.contains(&value) {
+            return Err("Can't convert a list or map that contains itself to JSON.".to_owned());
+        }
+        if self.seen.len() == MAX_DEPTH {
+            return Err("Too deeply nested to convert to JSON.".to_owned());
+        }
+
+        self.seen.push(value);
+        self.output.push(open);
+        Ok(())
+    }
+
+    fn end(&mut self, close: char, is_empty: bool) {
+        self.seen.pop();
+        if !is_empty {
+            self.newline();
+        }
+        self.output.push(close);
+    }
+
+    fn newline(&mut self) {
+        if let Some(indent) = self.indent {
+            self.output.push('\n');
+            self.output += &" ".repeat(indent * self.seen.len());
+        }
+    }
+
+    fn string(&mut self, string: &str) {
+        self.output.push('"');
+        for ch in string.chars() {
+            match ch {
+                '"' => self.output += "\\\"",
+                '\\' => self.output += "\\\\",
+                '\n' => self.output += "\\n",
+                '\r' => self.output += "\\r",
+                '\t' => self.output += "\\t",
+                '\u{8}' => self.output += "\\b",
+                '\u{c}' => self.output += "\\f",
+                ch if ch < ' ' => {
+                    let _ = write!(self.output, "\\u{:04x}", ch as u32);
+                }
+                ch => self.output.push(ch),
+            }
+        }
+        self.output.push('"');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::List;
+
+    fn parse_string(source: &str) -> Result<String, String> {
+        match parse(source) {
+            Ok(Json::String(string)) => Ok(string),
+            Ok(_) => panic!("{} isn't a string", source),
+            Err(error) => Err(error.message),
+        }
+    }
+
+    fn nested_lists(depth: usize, gc: &mut Gc) -> Value {
+        let mut value = Value::List(gc.alloc(List::new(Vec::new())));
+        for _ in 1..depth {
+            value = Value::List(gc.alloc(List::new(vec![value])));
+        }
+        value
+    }
+
+    #[test]
+    fn escapes_round_trip() {
+        let mut gc = Gc::quiet();
+        let string = "quote \" backslash \\ slash / \n\r\t\u{8}\u{c} \u{1} \u{1f} é 😀";
+        let value = Value::String(gc.intern(string.to_owned()));
+
+        let json = stringify(value, &gc, None).unwrap();
+        assert_eq!(
+            json,
+            r#""quote \" backslash \\ slash / \n\r\t\b\f \u0001 \u001f é 😀""#
+        );
+        assert_eq!(parse_string(&json).unwrap(), string);
+        assert_eq!(parse_string(r#""\/\u00e9\u0041""#).unwrap(), "/éA");
+    }
+
+    #[test]
+    fn parses_surrogate_pairs() {
+        assert_eq!(parse_string(r#""\ud83d\ude00""#).unwrap(), "😀");
+        assert_eq!(parse_string(r#""a\uD83D\uDE00b""#).unwrap(), "a😀b");
+    }
+
+    #[test]
+    fn rejects_unpaired_surrogates() {
+        let errors = [
+            (r#""\ud83d""#, "Expected a low surrogate escape"),
+            (r#""\ud83dx""#, "Expected a low surrogate escape"),
+            (r#""\ud83d\u0041""#, "Invalid low surrogate"),
+            (r#""\ud83d\ud83d""#, "Invalid low surrogate"),
+            (r#""\ude00""#, "Invalid Unicode escape"),
+        ];
+        for (source, message) in errors {
+            assert_eq!(parse_string(source), Err(message.to_owned()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn limits_parse_depth() {
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(parse(&nested(MAX_DEPTH)).is_ok());
+
+        let error = parse(&nested(MAX_DEPTH + 1)).err().unwrap();
+        assert_eq!(error.message, "Too deeply nested");
+        assert_eq!(error.column, MAX_DEPTH as u32 + 1);
+
+        let objects = r#"{"a":"#.repeat(MAX_DEPTH + 1) + "1" + &"}".repeat(MAX_DEPTH + 1);
+        assert_eq!(parse(&objects).err().unwrap().message, "Too deeply nested");
+    }
+
+    #[test]
+    fn limits_stringify_depth() {
+        let mut gc = Gc::quiet();
+        let value = nested_lists(MAX_DEPTH, &mut gc);
+        let json = stringify(value, &gc, Some(2)).unwrap();
+        assert!(json.starts_with("[\n  [\n    ["));
+
+        let value = nested_lists(MAX_DEPTH + 1, &mut gc);
+        assert_eq!(
+            stringify(value, &gc, None),
+            Err("Too deeply nested to convert to JSON.".to_owned())
+        );
+    }
+}
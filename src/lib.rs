@@ -1,10 +1,13 @@
+pub mod bytecode;
 pub mod chunk;
 mod compiler;
-mod gc;
+pub mod error;
+pub mod gc;
+mod json;
 mod native;
 mod scanner;
 mod table;
-mod value;
+pub mod value;
 pub mod vm;
 
 #[cfg(any(feature = "debug_print_code", feature = "debug_trace_execution"))]
@@ -1,44 +1,132 @@
 use std::io::Write;
+use std::path::Path;
 use std::process::exit;
 use std::{env, fs, io};
 
-use rox::vm::{InterpretResult, VM};
+use rox::bytecode::is_bytecode;
+use rox::error::InterpretError;
+use rox::vm::{VMBuilder, VM};
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut builder = VM::builder();
+    let mut paths = Vec::new();
+    let mut script_args = Vec::new();
+    let mut output = None;
+    let mut terse = false;
 
-    if args.len() == 1 {
-        repl();
-    } else if args.len() == 2 {
-        run_file(&args[1]);
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-frames" => builder = builder.max_frames(parse_limit(args.next())),
+            "--max-stack" => builder = builder.max_stack(parse_limit(args.next())),
+            "--max-nested-calls" => builder = builder.max_nested_calls(parse_limit(args.next())),
+            "--terse" => terse = true,
+            "-o" => output = Some(args.next().unwrap_or_else(|| usage())),
+            _ if !arg.starts_with('-') => {
+                paths.push(arg);
+                // Everything after the script's path is for the script.
+                if paths[0] != "compile" {
+                    script_args.extend(args.by_ref());
+                }
+            }
+            _ => usage(),
+        }
+    }
+
+    if !terse {
+        builder = builder.on_warning(|warning| eprintln!("{:#}", warning));
+    }
+
+    match (paths.as_slice(), output) {
+        ([], None) => repl(builder, terse),
+        ([path], None) => run_file(path, builder.args(script_args), terse),
+        ([command, path], output) if command == "compile" => {
+            let output = output.unwrap_or_else(|| {
+                let output = Path::new(path).with_extension("loxc");
+                output.to_string_lossy().into_owned()
+            });
+            compile_file(path, &output, builder, terse)
+        }
+        _ => usage(),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: clox [--max-frames n] [--max-stack n] [--max-nested-calls n] [--terse] [path [args...]]");
+    eprintln!("       clox compile [--terse] path [-o output]");
+    exit(64);
+}
+
+/// Prints an error with the offending source line, or only the message and
+/// line numbers like clox does if `terse` is set.
+fn report(error: &InterpretError, terse: bool) {
+    if terse {
+        eprintln!("{}", error);
     } else {
-        eprintln!("Usage: clox [path]");
-        exit(64);
+        eprintln!("{:#}", error);
     }
 }
 
-fn repl() {
-    let mut vm = VM::new();
+fn parse_limit(arg: Option<String>) -> usize {
+    match arg.and_then(|arg| arg.parse().ok()) {
+        Some(limit) if limit > 0 => limit,
+        _ => usage(),
+    }
+}
+
+fn repl(builder: VMBuilder, terse: bool) {
+    let mut vm = builder.build();
 
     let mut line = String::with_capacity(1024);
     loop {
         print!("> ");
         io::stdout().flush().unwrap();
-        if io::stdin().read_line(&mut line).is_err() {
+        if matches!(io::stdin().read_line(&mut line), Err(_) | Ok(0)) {
             println!();
             break;
         }
-        vm.interpret(&line);
+        match vm.interpret(&line) {
+            Ok(()) => (),
+            Err(InterpretError::Exit(code)) => exit(code),
+            Err(error) => report(&error, terse),
+        }
         line.clear()
     }
 }
 
-fn run_file(path: &str) {
-    let contents = fs::read_to_string(path).expect("Could not read the file.");
-    let mut vm = VM::new();
-    match vm.interpret(&contents) {
-        InterpretResult::Ok => (),
-        InterpretResult::CompileError => exit(65),
-        InterpretResult::RuntimeError => exit(70),
+fn run_file(path: &str, builder: VMBuilder, terse: bool) {
+    let contents = fs::read(path).expect("Could not read the file.");
+    let import_root = Path::new(path).parent().unwrap_or(Path::new(""));
+    let mut vm = builder.import_root(import_root).build();
+    let result = if is_bytecode(&contents) {
+        vm.load_bytecode(&contents)
+    } else {
+        let source = String::from_utf8(contents).expect("Could not read the file.");
+        vm.interpret(&source)
+    };
+
+    if let Err(error) = result {
+        let code = match error {
+            InterpretError::Compile(_) | InterpretError::Bytecode(_) => 65,
+            InterpretError::Runtime(_) => 70,
+            InterpretError::Exit(code) => exit(code),
+        };
+
+        report(&error, terse);
+        exit(code);
+    }
+}
+
+/// Compiles the script at `path` to bytecode that can be run later without
+/// compiling it again.
+fn compile_file(path: &str, output: &str, builder: VMBuilder, terse: bool) {
+    let source = fs::read_to_string(path).expect("Could not read the file.");
+    let mut vm = builder.build();
+    match vm.compile_bytecode(&source) {
+        Ok(bytecode) => fs::write(output, bytecode).expect("Could not write the file."),
+        Err(error) => {
+            report(&error, terse);
+            exit(65);
+        }
     }
 }
@@ -1,11 +1,472 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::error::RuntimeError;
+use crate::gc::GcRef;
+use crate::json::{self, Json};
 use crate::value::Value;
+use crate::vm::NativeContext;
 
-pub fn clock_native(_arg_coun: usize, _values: &[Value]) -> Value {
-    SystemTime::now()
+pub fn clock_native(_ctx: &mut NativeContext, _args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_secs_f64()
-        .into()
+        .into())
+}
+
+pub fn error_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(ctx.new_error(args[0]))
+}
+
+pub fn len_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let len = match args[0] {
+        Value::List(list) => ctx.gc().deref(list).items.len(),
+        Value::Map(map) => ctx.gc().deref(map).entries.len(),
+        Value::String(string) => ctx.gc().deref(string).chars().count(),
+        _ => return Err(ctx.error("Can only take the length of lists, maps and strings.")),
+    };
+
+    Ok((len as f64).into())
+}
+
+pub fn str_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string = ctx.stringify(args[0])?;
+    Ok(ctx.new_string(string))
+}
+
+pub fn keys_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    if let Value::Map(map) = args[0] {
+        let keys = ctx
+            .gc()
+            .deref(map)
+            .entries
+            .iter()
+            .map(|&(key, _)| key)
+            .collect();
+        Ok(ctx.new_list(keys))
+    } else {
+        Err(ctx.error("Can only take the keys of a map."))
+    }
+}
+
+pub fn values_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    if let Value::Map(map) = args[0] {
+        let values = ctx
+            .gc()
+            .deref(map)
+            .entries
+            .iter()
+            .map(|&(_, value)| value)
+            .collect();
+        Ok(ctx.new_list(values))
+    } else {
+        Err(ctx.error("Can only take the values of a map."))
+    }
+}
+
+type MathFn = fn(f64) -> f64;
+
+/// The math natives that take one number, with the function computing them.
+pub const MATH_FUNCTIONS: [(&str, MathFn); 15] = [
+    ("sqrt", f64::sqrt),
+    ("floor", f64::floor),
+    ("ceil", f64::ceil),
+    ("round", f64::round),
+    ("abs", f64::abs),
+    ("sin", f64::sin),
+    ("cos", f64::cos),
+    ("tan", f64::tan),
+    ("asin", f64::asin),
+    ("acos", f64::acos),
+    ("atan", f64::atan),
+    ("exp", f64::exp),
+    ("log", f64::ln),
+    ("log2", f64::log2),
+    ("log10", f64::log10),
+];
+
+/// Checks that an argument of the native `name` is a number.
+pub fn number_arg(ctx: &NativeContext, name: &str, arg: Value) -> Result<f64, RuntimeError> {
+    match arg {
+        Value::Number(number) => Ok(number),
+        _ => Err(ctx.error(&format!("'{}' expects a number argument.", name))),
+    }
+}
+
+pub fn pow_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let base = number_arg(ctx, "pow", args[0])?;
+    let exponent = number_arg(ctx, "pow", args[1])?;
+    Ok(base.powf(exponent).into())
+}
+
+pub fn atan2_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let y = number_arg(ctx, "atan2", args[0])?;
+    let x = number_arg(ctx, "atan2", args[1])?;
+    Ok(y.atan2(x).into())
+}
+
+pub fn min_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let min = args.iter().try_fold(f64::INFINITY, |min, &arg| {
+        Ok::<_, RuntimeError>(min.min(number_arg(ctx, "min", arg)?))
+    })?;
+    Ok(min.into())
+}
+
+pub fn max_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let max = args.iter().try_fold(f64::NEG_INFINITY, |max, &arg| {
+        Ok::<_, RuntimeError>(max.max(number_arg(ctx, "max", arg)?))
+    })?;
+    Ok(max.into())
+}
+
+/// Checks that an argument of the native `name` is a string.
+pub fn string_arg(
+    ctx: &NativeContext,
+    name: &str,
+    arg: Value,
+) -> Result<GcRef<String>, RuntimeError> {
+    match arg {
+        Value::String(string) => Ok(string),
+        _ => Err(ctx.error(&format!("'{}' expects a string argument.", name))),
+    }
+}
+
+/// Checks that an argument of the native `name` is a count or character
+/// index, a whole number that isn't negative.
+fn index_arg(ctx: &NativeContext, name: &str, arg: Value) -> Result<usize, RuntimeError> {
+    match number_arg(ctx, name, arg)? {
+        index if index >= 0.0 && index.fract() == 0.0 => Ok(index as usize),
+        _ => Err(ctx.error(&format!("'{}' expects a non-negative integer.", name))),
+    }
+}
+
+pub fn substring_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string = string_arg(ctx, "substring", args[0])?;
+    let length = ctx.gc().deref(string).chars().count();
+    let start = index_arg(ctx, "substring", args[1])?;
+    let end = match args.get(2) {
+        Some(&end) => index_arg(ctx, "substring", end)?,
+        None => length,
+    };
+
+    if start > end || end > length {
+        return Err(ctx.error("Substring range out of bounds."));
+    }
+
+    let substring: String = ctx
+        .gc()
+        .deref(string)
+        .chars()
+        .skip(start)
+        .take(end - start)
+        .collect();
+    Ok(ctx.new_string(substring))
+}
+
+pub fn index_of_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string: &String = ctx.gc().deref(string_arg(ctx, "indexOf", args[0])?);
+    let search: &String = ctx.gc().deref(string_arg(ctx, "indexOf", args[1])?);
+    let index = match string.find(search.as_str()) {
+        Some(offset) => string[..offset].chars().count() as f64,
+        None => -1.0,
+    };
+
+    Ok(index.into())
+}
+
+pub fn contains_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string: &String = ctx.gc().deref(string_arg(ctx, "contains", args[0])?);
+    let search: &String = ctx.gc().deref(string_arg(ctx, "contains", args[1])?);
+    Ok(string.contains(search.as_str()).into())
+}
+
+pub fn starts_with_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string: &String = ctx.gc().deref(string_arg(ctx, "startsWith", args[0])?);
+    let prefix: &String = ctx.gc().deref(string_arg(ctx, "startsWith", args[1])?);
+    Ok(string.starts_with(prefix.as_str()).into())
+}
+
+pub fn ends_with_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string: &String = ctx.gc().deref(string_arg(ctx, "endsWith", args[0])?);
+    let suffix: &String = ctx.gc().deref(string_arg(ctx, "endsWith", args[1])?);
+    Ok(string.ends_with(suffix.as_str()).into())
+}
+
+/// Splits a string on a separator, or into its characters if the separator
+/// is empty.
+pub fn split_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string: &String = ctx.gc().deref(string_arg(ctx, "split", args[0])?);
+    let separator: &String = ctx.gc().deref(string_arg(ctx, "split", args[1])?);
+    let parts: Vec<String> = if separator.is_empty() {
+        string.chars().map(String::from).collect()
+    } else {
+        string.split(separator.as_str()).map(String::from).collect()
+    };
+
+    let parts = parts.into_iter().map(|part| ctx.new_string(part)).collect();
+    Ok(ctx.new_list(parts))
+}
+
+/// Joins the items of a list into a string, converting them the way `print`
+/// does.
+pub fn join_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let Value::List(list) = args[0] else {
+        return Err(ctx.error("'join' expects a list argument."));
+    };
+    let separator = string_arg(ctx, "join", args[1])?;
+
+    let mut string = String::new();
+    let mut i = 0;
+    while let Some(&item) = ctx.gc().deref(list).items.get(i) {
+        if i > 0 {
+            let separator: &String = ctx.gc().deref(separator);
+            string.push_str(separator);
+        }
+        string += &ctx.stringify(item)?;
+        i += 1;
+    }
+
+    Ok(ctx.new_string(string))
+}
+
+pub fn trim_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string: &String = ctx.gc().deref(string_arg(ctx, "trim", args[0])?);
+    let trimmed = string.trim().to_owned();
+    Ok(ctx.new_string(trimmed))
+}
+
+pub fn upper_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string: &String = ctx.gc().deref(string_arg(ctx, "upper", args[0])?);
+    let upper = string.to_uppercase();
+    Ok(ctx.new_string(upper))
+}
+
+pub fn lower_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string: &String = ctx.gc().deref(string_arg(ctx, "lower", args[0])?);
+    let lower = string.to_lowercase();
+    Ok(ctx.new_string(lower))
+}
+
+pub fn replace_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string: &String = ctx.gc().deref(string_arg(ctx, "replace", args[0])?);
+    let from: &String = ctx.gc().deref(string_arg(ctx, "replace", args[1])?);
+    let to: &String = ctx.gc().deref(string_arg(ctx, "replace", args[2])?);
+    let replaced = string.replace(from.as_str(), to);
+    Ok(ctx.new_string(replaced))
+}
+
+/// The longest string `repeat` will build, in bytes.
+const MAX_REPEAT_LENGTH: usize = 1 << 30;
+
+pub fn repeat_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string = string_arg(ctx, "repeat", args[0])?;
+    let count = index_arg(ctx, "repeat", args[1])?;
+    let string: &String = ctx.gc().deref(string);
+    match string.len().checked_mul(count) {
+        Some(length) if length <= MAX_REPEAT_LENGTH => {
+            let repeated = string.repeat(count);
+            Ok(ctx.new_string(repeated))
+        }
+        _ => Err(ctx.error("Repeated string is too long.")),
+    }
+}
+
+/// Makes a one character string from a Unicode code point.
+pub fn chr_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let code = index_arg(ctx, "chr", args[0])?;
+    match u32::try_from(code).ok().and_then(char::from_u32) {
+        Some(ch) => Ok(ctx.new_string(ch)),
+        None => Err(ctx.error("Invalid Unicode code point.")),
+    }
+}
+
+/// Returns the Unicode code point of a one character string.
+pub fn ord_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string: &String = ctx.gc().deref(string_arg(ctx, "ord", args[0])?);
+    let mut chars = string.chars();
+    match (chars.next(), chars.next()) {
+        (Some(ch), None) => Ok((ch as u32 as f64).into()),
+        _ => Err(ctx.error("'ord' expects a string of one character.")),
+    }
+}
+
+/// Checks that the filesystem is enabled and that an argument of the native
+/// `name` is a path.
+fn path_arg(ctx: &NativeContext, name: &str, arg: Value) -> Result<String, RuntimeError> {
+    if !ctx.allows_filesystem() {
+        return Err(ctx.error("Filesystem access is disabled."));
+    }
+
+    let path = string_arg(ctx, name, arg)?;
+    Ok(ctx.gc().deref(path).clone())
+}
+
+fn io_error(ctx: &NativeContext, action: &str, path: &str, error: io::Error) -> RuntimeError {
+    ctx.error(&format!("Could not {} '{}': {}.", action, path, error))
+}
+
+pub fn read_file_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = path_arg(ctx, "readFile", args[0])?;
+    match fs::read_to_string(&path) {
+        Ok(contents) => Ok(ctx.new_string(contents)),
+        Err(error) => Err(io_error(ctx, "read", &path, error)),
+    }
+}
+
+/// Opens a file and returns a native that reads its next line each time it's
+/// called, or returns nil at the end of the file.
+pub fn read_lines_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = path_arg(ctx, "readLines", args[0])?;
+    let file = File::open(&path).map_err(|error| io_error(ctx, "open", &path, error))?;
+    let lines = RefCell::new(BufReader::new(file).lines());
+
+    Ok(ctx.new_native("nextLine", 0, move |ctx, _args| {
+        match lines.borrow_mut().next() {
+            Some(Ok(line)) => Ok(ctx.new_string(line)),
+            Some(Err(error)) => Err(io_error(ctx, "read", &path, error)),
+            None => Ok(Value::Nil),
+        }
+    }))
+}
+
+pub fn write_file_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = path_arg(ctx, "writeFile", args[0])?;
+    let contents: &String = ctx.gc().deref(string_arg(ctx, "writeFile", args[1])?);
+    match fs::write(&path, contents) {
+        Ok(()) => Ok(Value::Nil),
+        Err(error) => Err(io_error(ctx, "write", &path, error)),
+    }
+}
+
+pub fn append_file_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = path_arg(ctx, "appendFile", args[0])?;
+    let contents: &String = ctx.gc().deref(string_arg(ctx, "appendFile", args[1])?);
+    let result = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()));
+
+    match result {
+        Ok(()) => Ok(Value::Nil),
+        Err(error) => Err(io_error(ctx, "write", &path, error)),
+    }
+}
+
+pub fn exists_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = path_arg(ctx, "exists", args[0])?;
+    Ok(fs::exists(&path).unwrap_or(false).into())
+}
+
+/// Lists the names of the entries in a directory, sorted.
+pub fn list_dir_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = path_arg(ctx, "listDir", args[0])?;
+    let names = fs::read_dir(&path).and_then(|entries| {
+        entries
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<io::Result<Vec<_>>>()
+    });
+
+    match names {
+        Ok(mut names) => {
+            names.sort();
+            let names = names.into_iter().map(|name| ctx.new_string(name)).collect();
+            Ok(ctx.new_list(names))
+        }
+        Err(error) => Err(io_error(ctx, "list", &path, error)),
+    }
+}
+
+/// Reads a line from stdin, or returns nil at the end of the input.
+pub fn read_line_native(ctx: &mut NativeContext, _args: &[Value]) -> Result<Value, RuntimeError> {
+    match ctx.read_line() {
+        Ok(Some(line)) => Ok(ctx.new_string(line)),
+        Ok(None) => Ok(Value::Nil),
+        Err(error) => Err(ctx.error(&format!("Could not read input: {}.", error))),
+    }
+}
+
+/// Returns the command-line arguments given after the script's path.
+pub fn args_native(ctx: &mut NativeContext, _args: &[Value]) -> Result<Value, RuntimeError> {
+    let args = ctx.args().to_vec();
+    let args = args.into_iter().map(|arg| ctx.new_string(arg)).collect();
+    Ok(ctx.new_list(args))
+}
+
+/// Returns the value of an environment variable, or nil if it isn't set.
+pub fn env_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let name: &String = ctx.gc().deref(string_arg(ctx, "env", args[0])?);
+    match env::var(name) {
+        Ok(value) => Ok(ctx.new_string(value)),
+        Err(_) => Ok(Value::Nil),
+    }
+}
+
+/// Stops the script with an exit code, 0 if none is given.
+pub fn exit_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let code = match args.first() {
+        Some(&code) => number_arg(ctx, "exit", code)?,
+        None => 0.0,
+    };
+
+    if code.fract() != 0.0 || code < i32::MIN as f64 || code > i32::MAX as f64 {
+        return Err(ctx.error("'exit' expects an integer exit code."));
+    }
+
+    Err(ctx.exit(code as i32))
+}
+
+/// Parses a JSON string into nil, booleans, numbers, strings, lists and maps.
+pub fn json_parse_native(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let source: &String = ctx.gc().deref(string_arg(ctx, "json_parse", args[0])?);
+    match json::parse(source) {
+        Ok(json) => json_value(ctx, json),
+        Err(error) => Err(ctx.error(&error.to_string())),
+    }
+}
+
+fn json_value(ctx: &mut NativeContext, json: Json) -> Result<Value, RuntimeError> {
+    Ok(match json {
+        Json::Null => Value::Nil,
+        Json::Bool(value) => value.into(),
+        Json::Number(value) => value.into(),
+        Json::String(value) => ctx.new_string(value),
+        Json::Array(items) => {
+            let items = items
+                .into_iter()
+                .map(|item| json_value(ctx, item))
+                .collect::<Result<_, _>>()?;
+            ctx.new_list(items)
+        }
+        Json::Object(entries) => {
+            let entries = entries
+                .into_iter()
+                .map(|(key, value)| Ok((ctx.new_string(key), json_value(ctx, value)?)))
+                .collect::<Result<_, RuntimeError>>()?;
+            ctx.new_map(entries)?
+        }
+    })
+}
+
+/// Writes a value as JSON, indented by the given number of spaces per level
+/// if there's a second argument.
+pub fn json_stringify_native(
+    ctx: &mut NativeContext,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let indent = match args.get(1) {
+        Some(&indent) => {
+            Some(index_arg(ctx, "json_stringify", indent)?).filter(|&indent| indent > 0)
+        }
+        None => None,
+    };
+
+    match json::stringify(args[0], ctx.gc(), indent) {
+        Ok(string) => Ok(ctx.new_string(string)),
+        Err(message) => Err(ctx.error(&message)),
+    }
 }
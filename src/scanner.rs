@@ -1,13 +1,20 @@
 use itertools::{multipeek, MultiPeek};
 
+use crate::chunk::Span;
+
 use std::str::Chars;
 
+#[derive(Clone)]
 pub struct Scanner<'a> {
     text: &'a str,
     source: MultiPeek<Chars<'a>>,
     start: usize,
     current: usize,
     pub line: u32,
+    column: u32,
+    start_column: u32,
+    /// The brace depth inside each string interpolation being scanned.
+    interpolations: Vec<u32>,
     is_finished: bool,
 }
 
@@ -19,6 +26,9 @@ impl<'a> Scanner<'a> {
             start: 0,
             current: 0,
             line: 1,
+            column: 1,
+            start_column: 1,
+            interpolations: Vec::new(),
             is_finished: false,
         }
     }
@@ -27,14 +37,33 @@ impl<'a> Scanner<'a> {
         use TokenType::*;
 
         self.start = self.current;
+        self.start_column = self.column;
 
         if let Some(ch) = self.advance() {
             match ch {
                 '(' => self.make_token(LeftParen),
                 ')' => self.make_token(RightParen),
-                '{' => self.make_token(LeftBrace),
-                '}' => self.make_token(RightBrace),
+                '{' => {
+                    if let Some(depth) = self.interpolations.last_mut() {
+                        *depth += 1;
+                    }
+                    self.make_token(LeftBrace)
+                }
+                '}' => match self.interpolations.last_mut() {
+                    Some(0) => {
+                        self.interpolations.pop();
+                        self.scan_string()
+                    }
+                    Some(depth) => {
+                        *depth -= 1;
+                        self.make_token(RightBrace)
+                    }
+                    None => self.make_token(RightBrace),
+                },
+                '[' => self.make_token(LeftBracket),
+                ']' => self.make_token(RightBracket),
                 ';' => self.make_token(Semicolon),
+                ':' => self.make_token(Colon),
                 ',' => self.make_token(Comma),
                 '.' => self.make_token(Dot),
                 '-' => self.make_token(Minus),
@@ -42,7 +71,15 @@ impl<'a> Scanner<'a> {
                 '/' => self.scan_comment(),
                 '*' => self.make_token(Star),
                 '!' => self.match_token('=', BangEqual, Bang),
-                '=' => self.match_token('=', EqualEqual, Equal),
+                '=' => {
+                    if self.source.peek() == Some(&'>') {
+                        self.advance();
+                        self.make_token(Arrow)
+                    } else {
+                        self.source.reset_peek();
+                        self.match_token('=', EqualEqual, Equal)
+                    }
+                }
                 '<' => self.match_token('=', LessEqual, Less),
                 '>' => self.match_token('=', GreaterEqual, Greater),
                 ' ' | '\t' | '\r' => self.scan_token(),
@@ -64,21 +101,28 @@ impl<'a> Scanner<'a> {
     }
 
     fn advance(&mut self) -> Option<char> {
-        self.source.next().map(|x| {
+        self.source.next().inspect(|&x| {
             self.current += x.len_utf8();
-            x
+            if x == '\n' {
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
         })
     }
 
     fn make_token(&self, token_type: TokenType) -> Option<Token<'a>> {
         Some(Token {
             kind: token_type,
-            value: if token_type == TokenType::String {
-                &self.text[self.start + 1..self.current - 1]
-            } else {
-                &self.text[self.start..self.current]
+            value: match token_type {
+                TokenType::String => &self.text[self.start + 1..self.current - 1],
+                TokenType::Interpolation => &self.text[self.start + 1..self.current - 2],
+                _ => &self.text[self.start..self.current],
             },
             line: self.line,
+            column: self.start_column,
+            offset: self.start,
+            length: self.current - self.start,
         })
     }
 
@@ -95,6 +139,9 @@ impl<'a> Scanner<'a> {
         Some(Token {
             kind: TokenType::Error,
             line: self.line,
+            column: self.start_column,
+            offset: self.start,
+            length: self.current - self.start,
             value: message,
         })
     }
@@ -102,7 +149,7 @@ impl<'a> Scanner<'a> {
     fn scan_comment(&mut self) -> Option<Token<'a>> {
         if self.source.peek() == Some(&'/') {
             self.advance();
-            while self.source.peek().map_or(false, |&ch| ch != '\n') {
+            while self.source.peek().is_some_and(|&ch| ch != '\n') {
                 self.advance();
             }
 
@@ -112,13 +159,36 @@ impl<'a> Scanner<'a> {
         }
     }
 
+    /// Scans the rest of a string literal, or of its part up to the next
+    /// interpolated expression.
     fn scan_string(&mut self) -> Option<Token<'a>> {
-        while self.source.peek().map_or(false, |&ch| ch != '"') {
+        let mut error = None;
+
+        while self.source.peek().is_some_and(|&ch| ch != '"') {
             self.source.reset_peek();
-            if self.source.peek() == Some(&'\n') {
-                self.line += 1;
+            match self.advance() {
+                Some('\n') => self.line += 1,
+                Some('$') if self.source.peek() == Some(&'{') => {
+                    self.advance();
+                    self.interpolations.push(0);
+                    return error.or_else(|| self.make_token(TokenType::Interpolation));
+                }
+                Some('\\') => {
+                    let (line, column, offset) = (self.line, self.column - 1, self.current - 1);
+                    if let Err(message) = self.scan_escape() {
+                        error.get_or_insert(Token {
+                            kind: TokenType::Error,
+                            value: message,
+                            line,
+                            column,
+                            offset,
+                            length: self.current - offset,
+                        });
+                    }
+                    self.source.reset_peek();
+                }
+                _ => self.source.reset_peek(),
             }
-            self.advance();
         }
 
         self.source.reset_peek();
@@ -127,21 +197,63 @@ impl<'a> Scanner<'a> {
         }
 
         self.advance();
-        self.make_token(TokenType::String)
+        error.or_else(|| self.make_token(TokenType::String))
+    }
+
+    fn scan_escape(&mut self) -> Result<(), &'static str> {
+        match self.source.peek() {
+            Some('n' | 't' | 'r' | '\\' | '"' | '0' | '$') => {
+                self.advance();
+                Ok(())
+            }
+            Some('u') => {
+                self.advance();
+                self.scan_unicode_escape()
+            }
+            _ => Err("Invalid escape sequence."),
+        }
+    }
+
+    fn scan_unicode_escape(&mut self) -> Result<(), &'static str> {
+        const INVALID: &str = "Invalid Unicode escape sequence.";
+
+        if self.source.peek() != Some(&'{') {
+            return Err(INVALID);
+        }
+        self.advance();
+
+        let mut digits = String::new();
+        loop {
+            match self.source.peek() {
+                Some(&ch) if ch.is_ascii_hexdigit() => digits.push(ch),
+                Some('}') => break,
+                _ => return Err(INVALID),
+            }
+            self.advance();
+        }
+        self.advance();
+
+        match u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+        {
+            Some(_) if digits.len() <= 6 => Ok(()),
+            _ => Err(INVALID),
+        }
     }
 
     fn scan_number(&mut self) -> Option<Token<'a>> {
-        while self.source.peek().map_or(false, |ch| ch.is_ascii_digit()) {
+        while self.source.peek().is_some_and(|ch| ch.is_ascii_digit()) {
             self.advance();
         }
 
         self.source.reset_peek();
 
         if self.source.peek() == Some(&'.')
-            && self.source.peek().map_or(false, |ch| ch.is_ascii_digit())
+            && self.source.peek().is_some_and(|ch| ch.is_ascii_digit())
         {
             self.advance();
-            while self.source.peek().map_or(false, |ch| ch.is_ascii_digit()) {
+            while self.source.peek().is_some_and(|ch| ch.is_ascii_digit()) {
                 self.advance();
             }
         }
@@ -153,7 +265,7 @@ impl<'a> Scanner<'a> {
         while self
             .source
             .peek()
-            .map_or(false, |&ch| ch.is_ascii_alphanumeric() || ch == '_')
+            .is_some_and(|&ch| ch.is_ascii_alphanumeric() || ch == '_')
         {
             self.advance();
         }
@@ -166,19 +278,27 @@ impl<'a> Scanner<'a> {
 
         match &self.text[self.start..self.current] {
             "and" => And,
+            "break" => Break,
+            "catch" => Catch,
             "class" => Class,
+            "continue" => Continue,
             "else" => Else,
             "false" => False,
+            "finally" => Finally,
             "for" => For,
+            "from" => From,
             "fun" => Fun,
             "if" => If,
+            "import" => Import,
             "nil" => Nil,
             "or" => Or,
             "print" => Print,
             "return" => Return,
             "super" => Super,
             "this" => This,
+            "throw" => Throw,
             "true" => True,
+            "try" => Try,
             "var" => Var,
             "while" => While,
             _ => Identifier,
@@ -186,6 +306,40 @@ impl<'a> Scanner<'a> {
     }
 }
 
+/// Decodes the escape sequences in the contents of a string literal the
+/// scanner has already checked.
+pub fn unescape(literal: &str) -> String {
+    let mut result = String::with_capacity(literal.len());
+    let mut chars = literal.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('0') => result.push('\0'),
+            Some('u') => {
+                let digits: String = chars.by_ref().skip(1).take_while(|&ch| ch != '}').collect();
+                if let Some(ch) = u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                {
+                    result.push(ch);
+                }
+            }
+            Some(ch) => result.push(ch),
+            None => (),
+        }
+    }
+
+    result
+}
+
 impl<'a> Iterator for Scanner<'a> {
     type Item = Token<'a>;
 
@@ -201,10 +355,13 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
     Plus,
+    Colon,
     Semicolon,
     Slash,
     Star,
@@ -217,25 +374,35 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    Arrow,
 
     Identifier,
     String,
+    Interpolation,
     Number,
 
     And,
+    Break,
+    Catch,
     Class,
+    Continue,
     Else,
     False,
+    Finally,
     For,
+    From,
     Fun,
     If,
+    Import,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
@@ -248,6 +415,23 @@ pub struct Token<'a> {
     pub kind: TokenType,
     pub value: &'a str,
     pub line: u32,
+    pub column: u32,
+    /// Byte offset of the token in the source.
+    pub offset: usize,
+    /// Length of the token in the source, which can differ from `value` for
+    /// strings and errors.
+    pub length: usize,
+}
+
+impl<'a> Token<'a> {
+    pub fn span(&self) -> Span {
+        Span {
+            offset: self.offset,
+            length: self.length,
+            line: self.line,
+            column: self.column,
+        }
+    }
 }
 
 impl<'a> Default for Token<'a> {
@@ -256,6 +440,9 @@ impl<'a> Default for Token<'a> {
             kind: TokenType::Eof,
             value: "",
             line: 1,
+            column: 1,
+            offset: 0,
+            length: 0,
         }
     }
 }
@@ -1,9 +1,14 @@
+use std::collections::HashMap;
 use std::fmt::{Debug, Display};
 use std::mem;
+use std::path::PathBuf;
+use std::rc::Rc;
 
 use crate::chunk::{Chunk, OpCode};
+use crate::error::RuntimeError;
 use crate::gc::{GcRef, GcTrace};
 use crate::table::Table;
+use crate::vm::NativeContext;
 
 impl GcTrace for String {
     fn format(&self, f: &mut std::fmt::Formatter, _gc: &crate::gc::Gc) -> std::fmt::Result {
@@ -11,7 +16,7 @@ impl GcTrace for String {
     }
 
     fn size(&self) -> usize {
-        mem::size_of::<String>() + self.as_bytes().len()
+        mem::size_of::<String>() + self.len()
     }
 
     fn trace(&self, _gc: &mut crate::gc::Gc) {}
@@ -32,11 +37,14 @@ pub enum Value {
     Bool(bool),
     Number(f64),
     String(GcRef<String>),
-    NativeFunction(Native),
+    NativeFunction(GcRef<NativeFunction>),
     Closure(GcRef<Closure>),
     Class(GcRef<Class>),
     Instance(GcRef<Instance>),
     BoundMethod(GcRef<BoundMethod>),
+    List(GcRef<List>),
+    Map(GcRef<Map>),
+    Module(GcRef<Module>),
 }
 
 impl Value {
@@ -53,7 +61,10 @@ impl GcTrace for Value {
             Value::Class(value) => gc.deref(*value).format(f, gc),
             Value::Closure(value) => gc.deref(*value).format(f, gc),
             Value::Instance(value) => gc.deref(*value).format(f, gc),
-            Value::NativeFunction(_) => write!(f, "<native fn>"),
+            Value::List(value) => gc.format_once(*value, f, "[...]"),
+            Value::Map(value) => gc.format_once(*value, f, "{...}"),
+            Value::Module(value) => gc.deref(*value).format(f, gc),
+            Value::NativeFunction(value) => gc.deref(*value).format(f, gc),
             Value::Nil => write!(f, "nil"),
             Value::Number(value) => write!(f, "{}", value),
             Value::String(value) => gc.deref(*value).format(f, gc),
@@ -70,6 +81,10 @@ impl GcTrace for Value {
             Value::Class(value) => gc.mark_object(*value),
             Value::Closure(value) => gc.mark_object(*value),
             Value::Instance(value) => gc.mark_object(*value),
+            Value::List(value) => gc.mark_object(*value),
+            Value::Map(value) => gc.mark_object(*value),
+            Value::Module(value) => gc.mark_object(*value),
+            Value::NativeFunction(value) => gc.mark_object(*value),
             Value::String(value) => gc.mark_object(*value),
             _ => (),
         }
@@ -86,16 +101,31 @@ impl GcTrace for Value {
 
 #[derive(Clone, Copy, Debug)]
 pub struct FnUpvalue {
-    pub index: u8,
+    pub index: u16,
     pub is_local: bool,
 }
 
+/// Where to jump when an error is raised by the instructions in
+/// `start..end` of a function's chunk.
+#[derive(Clone, Copy, Debug)]
+pub struct Handler {
+    pub start: usize,
+    pub end: usize,
+    pub target: usize,
+    /// The number of stack slots in the frame to keep when jumping.
+    pub depth: usize,
+}
+
 #[derive(Debug)]
 pub struct Function {
     pub arity: usize,
     pub chunk: Chunk,
     pub name: GcRef<String>,
     pub upvalues: Vec<FnUpvalue>,
+    pub handlers: Vec<Handler>,
+    /// The module whose globals the function uses, or `None` for the VM's
+    /// own globals.
+    pub module: Option<GcRef<Module>>,
 }
 
 impl Function {
@@ -104,6 +134,8 @@ impl Function {
             chunk: Chunk::new(),
             arity: 0,
             upvalues: Vec::new(),
+            handlers: Vec::new(),
+            module: None,
             name,
         }
     }
@@ -129,6 +161,10 @@ impl GcTrace for Function {
 
     fn trace(&self, gc: &mut crate::gc::Gc) {
         gc.mark_object(self.name);
+        if let Some(module) = self.module {
+            gc.mark_object(module);
+        }
+
         for &constant in &self.chunk.constants {
             gc.mark_value(constant);
         }
@@ -143,19 +179,87 @@ impl GcTrace for Function {
     }
 }
 
-#[derive(Clone, Copy)]
-pub struct Native(pub fn(usize, &[Value]) -> Value);
+/// The number of arguments a native function accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arity {
+    Exactly(usize),
+    Between(usize, usize),
+    AtLeast(usize),
+}
 
-impl PartialEq for Native {
-    fn eq(&self, other: &Self) -> bool {
-        std::ptr::eq(self, other)
+impl Arity {
+    pub fn accepts(self, arg_count: usize) -> bool {
+        match self {
+            Arity::Exactly(arity) => arg_count == arity,
+            Arity::Between(min, max) => (min..=max).contains(&arg_count),
+            Arity::AtLeast(min) => arg_count >= min,
+        }
     }
 }
 
-impl Debug for Native {
+impl From<usize> for Arity {
+    fn from(arity: usize) -> Self {
+        Arity::Exactly(arity)
+    }
+}
+
+impl Display for Arity {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arity::Exactly(arity) => write!(f, "{}", arity),
+            Arity::Between(min, max) => write!(f, "{} to {}", min, max),
+            Arity::AtLeast(min) => write!(f, "at least {}", min),
+        }
+    }
+}
+
+pub type NativeFn = dyn Fn(&mut NativeContext, &[Value]) -> Result<Value, RuntimeError>;
+
+pub struct NativeFunction {
+    pub name: GcRef<String>,
+    pub arity: Arity,
+    pub function: Rc<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(name: GcRef<String>, arity: Arity, function: Rc<NativeFn>) -> Self {
+        Self {
+            name,
+            arity,
+            function,
+        }
+    }
+}
+
+impl Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+impl GcTrace for NativeFunction {
+    fn format(&self, f: &mut std::fmt::Formatter, _gc: &crate::gc::Gc) -> std::fmt::Result {
         write!(f, "<native fn>")
     }
+
+    fn size(&self) -> usize {
+        mem::size_of::<Self>()
+    }
+
+    fn trace(&self, gc: &mut crate::gc::Gc) {
+        gc.mark_object(self.name);
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -242,6 +346,10 @@ impl GcTrace for Closure {
 pub struct Class {
     pub name: GcRef<String>,
     pub methods: Table,
+    /// Methods called on the class itself, declared with `class`.
+    pub statics: Table,
+    pub getters: Table,
+    pub setters: Table,
 }
 
 impl Class {
@@ -249,6 +357,9 @@ impl Class {
         Class {
             name,
             methods: Table::new(),
+            statics: Table::new(),
+            getters: Table::new(),
+            setters: Table::new(),
         }
     }
 }
@@ -266,6 +377,9 @@ impl GcTrace for Class {
     fn trace(&self, gc: &mut crate::gc::Gc) {
         gc.mark_object(self.name);
         gc.mark_table(&self.methods);
+        gc.mark_table(&self.statics);
+        gc.mark_table(&self.getters);
+        gc.mark_table(&self.setters);
     }
 
     fn as_any(&self) -> &dyn std::any::Any {
@@ -318,6 +432,51 @@ impl GcTrace for Instance {
     }
 }
 
+/// A script loaded with `import`. It has its own globals, which are the
+/// members it exports.
+#[derive(Debug)]
+pub struct Module {
+    pub name: GcRef<String>,
+    /// The canonical path of the script, which the imports in it are
+    /// resolved relative to.
+    pub path: PathBuf,
+    pub globals: Table,
+}
+
+impl Module {
+    pub fn new(name: GcRef<String>, path: PathBuf) -> Self {
+        Self {
+            name,
+            path,
+            globals: Table::new(),
+        }
+    }
+}
+
+impl GcTrace for Module {
+    fn format(&self, f: &mut std::fmt::Formatter, gc: &crate::gc::Gc) -> std::fmt::Result {
+        write!(f, "<module {}>", gc.deref(self.name))
+    }
+
+    fn size(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.globals.capacity() * (mem::size_of::<GcRef<String>>() + mem::size_of::<Value>())
+    }
+
+    fn trace(&self, gc: &mut crate::gc::Gc) {
+        gc.mark_object(self.name);
+        gc.mark_table(&self.globals);
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+}
+
 #[derive(Debug)]
 pub struct BoundMethod {
     pub receiver: Value,
@@ -354,6 +513,148 @@ impl GcTrace for BoundMethod {
     }
 }
 
+#[derive(Debug)]
+pub struct List {
+    pub items: Vec<Value>,
+}
+
+impl List {
+    pub fn new(items: Vec<Value>) -> Self {
+        Self { items }
+    }
+}
+
+impl GcTrace for List {
+    fn format(&self, f: &mut std::fmt::Formatter, gc: &crate::gc::Gc) -> std::fmt::Result {
+        write!(f, "[")?;
+        for (i, item) in self.items.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            item.format(f, gc)?;
+        }
+        write!(f, "]")
+    }
+
+    fn size(&self) -> usize {
+        mem::size_of::<Self>() + self.items.capacity() * mem::size_of::<Value>()
+    }
+
+    fn trace(&self, gc: &mut crate::gc::Gc) {
+        for &item in &self.items {
+            gc.mark_value(item);
+        }
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+}
+
+/// A `Value` usable as a map key. Numbers are keyed by their bits with `-0`
+/// folded into `0`, so keys agree with `==`; `NaN` is never equal to itself
+/// and is rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Nil,
+    Bool(bool),
+    Number(u64),
+    String(GcRef<String>),
+}
+
+impl TryFrom<Value> for MapKey {
+    type Error = &'static str;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Nil => Ok(MapKey::Nil),
+            Value::Bool(value) => Ok(MapKey::Bool(value)),
+            Value::Number(value) if value.is_nan() => Err("Map key can't be NaN."),
+            Value::Number(0.0) => Ok(MapKey::Number(0f64.to_bits())),
+            Value::Number(value) => Ok(MapKey::Number(value.to_bits())),
+            Value::String(value) => Ok(MapKey::String(value)),
+            _ => Err("Map key must be a number, string, boolean or nil."),
+        }
+    }
+}
+
+impl From<MapKey> for Value {
+    fn from(key: MapKey) -> Self {
+        match key {
+            MapKey::Nil => Value::Nil,
+            MapKey::Bool(value) => Value::Bool(value),
+            MapKey::Number(bits) => Value::Number(f64::from_bits(bits)),
+            MapKey::String(value) => Value::String(value),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Map {
+    pub entries: Vec<(Value, Value)>,
+    index: HashMap<MapKey, usize>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: MapKey) -> Option<Value> {
+        self.index.get(&key).map(|&i| self.entries[i].1)
+    }
+
+    pub fn insert(&mut self, key: MapKey, value: Value) {
+        match self.index.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.index.insert(key, self.entries.len());
+                self.entries.push((key.into(), value));
+            }
+        }
+    }
+}
+
+impl GcTrace for Map {
+    fn format(&self, f: &mut std::fmt::Formatter, gc: &crate::gc::Gc) -> std::fmt::Result {
+        write!(f, "{{")?;
+        for (i, (key, value)) in self.entries.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            key.format(f, gc)?;
+            write!(f, ": ")?;
+            value.format(f, gc)?;
+        }
+        write!(f, "}}")
+    }
+
+    fn size(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.entries.capacity() * mem::size_of::<(Value, Value)>()
+            + self.index.capacity() * (mem::size_of::<MapKey>() + mem::size_of::<usize>())
+    }
+
+    fn trace(&self, gc: &mut crate::gc::Gc) {
+        for &(key, value) in &self.entries {
+            gc.mark_value(key);
+            gc.mark_value(value);
+        }
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+}
+
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         <&Value as std::fmt::Debug>::fmt(&self, f)
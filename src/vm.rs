@@ -1,25 +1,437 @@
+use crate::bytecode;
 use crate::chunk::{Chunk, OpCode};
 use crate::compiler::compile;
+use crate::error::{CompileWarning, Frame, InterpretError, RuntimeError, SourceSpan};
 use crate::gc::{Gc, GcRef, GcTrace, GcTraceFormatter};
 use crate::native::*;
 use crate::table::Table;
-use crate::value::{BoundMethod, Class, Closure, Instance, Native, Upvalue, Value};
-
-use std::collections::hash_map::Entry;
-
-#[cfg(feature = "debug_trace_execution")]
+use crate::value::{
+    Arity, BoundMethod, Class, Closure, Function, Instance, List, Map, MapKey, Module,
+    NativeFunction, Upvalue, Value,
+};
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::f64;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+#[cfg(any(feature = "debug_print_code", feature = "debug_trace_execution"))]
 use crate::debug::Disassembler;
 
-const FRAME_MAX: usize = 64;
-const STACK_MAX: usize = FRAME_MAX * 256;
+const FRAME_MAX: usize = 64 * 1024;
+const STACK_MAX: usize = 1024 * 1024;
+/// Each call from Rust into Lox code, like a native calling back into a
+/// closure or a `toString` method, runs on the Rust stack, so their nesting
+/// is limited well below `FRAME_MAX`.
+const NESTED_CALL_MAX: usize = 128;
+
+type WarningHandler = dyn FnMut(&CompileWarning);
 
 pub struct VM {
     gc: Gc,
     frames: Vec<CallFrame>,
     stack: Vec<Value>,
     globals: Table,
+    /// The natives, visible from every module unless it defines a global with
+    /// the same name.
+    builtins: Table,
+    /// Every module imported so far, by canonical path.
+    modules: HashMap<PathBuf, GcRef<Module>>,
+    import_root: PathBuf,
     open_upvalues: Vec<GcRef<Upvalue>>,
     init_string: GcRef<String>,
+    to_string_string: GcRef<String>,
+    operators: Operators,
+    error_class: GcRef<Class>,
+    output: Box<dyn Write>,
+    #[cfg_attr(
+        not(any(
+            feature = "debug_print_code",
+            feature = "debug_trace_execution",
+            feature = "debug_log_gc"
+        )),
+        allow(dead_code)
+    )]
+    diagnostics: Rc<RefCell<dyn Write>>,
+    host_data: Option<Box<dyn Any>>,
+    input: Option<Box<dyn BufRead>>,
+    allow_filesystem: bool,
+    args: Vec<String>,
+    native_roots: Vec<Value>,
+    /// Values the host keeps alive, see [`VM::pin`].
+    pinned: Rc<RefCell<Pins>>,
+    max_frames: usize,
+    max_stack: usize,
+    max_nested_calls: usize,
+    /// How many calls from Rust into Lox code are running.
+    nested_calls: usize,
+    on_warning: Option<Box<WarningHandler>>,
+}
+
+/// Slots for the values held by [`Pinned`] guards. Freed slots are reused so
+/// pinning and unpinning are constant time.
+#[derive(Default)]
+struct Pins {
+    slots: Vec<Option<Value>>,
+    free: Vec<usize>,
+}
+
+impl Pins {
+    fn insert(&mut self, value: Value) -> usize {
+        match self.free.pop() {
+            Some(slot) => {
+                self.slots[slot] = Some(value);
+                slot
+            }
+            None => {
+                self.slots.push(Some(value));
+                self.slots.len() - 1
+            }
+        }
+    }
+
+    fn remove(&mut self, slot: usize) {
+        self.slots[slot] = None;
+        self.free.push(slot);
+    }
+}
+
+/// A value the host holds on to, returned by [`VM::call`] and [`VM::pin`]. It
+/// isn't collected until the guard is dropped.
+pub struct Pinned {
+    value: Value,
+    /// `None` for values that don't live on the heap.
+    slot: Option<usize>,
+    pins: Rc<RefCell<Pins>>,
+}
+
+impl Pinned {
+    pub fn value(&self) -> Value {
+        self.value
+    }
+}
+
+impl Clone for Pinned {
+    fn clone(&self) -> Self {
+        let slot = self.slot.map(|_| self.pins.borrow_mut().insert(self.value));
+        Pinned {
+            value: self.value,
+            slot,
+            pins: Rc::clone(&self.pins),
+        }
+    }
+}
+
+impl Drop for Pinned {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            self.pins.borrow_mut().remove(slot);
+        }
+    }
+}
+
+/// Configures a [`VM`] before it is created.
+pub struct VMBuilder {
+    output: Box<dyn Write>,
+    diagnostics: Rc<RefCell<dyn Write>>,
+    host_data: Option<Box<dyn Any>>,
+    input: Option<Box<dyn BufRead>>,
+    allow_filesystem: bool,
+    args: Vec<String>,
+    max_frames: usize,
+    max_stack: usize,
+    max_nested_calls: usize,
+    on_warning: Option<Box<WarningHandler>>,
+    import_root: PathBuf,
+}
+
+impl VMBuilder {
+    /// Where `print` statements write to. Defaults to stdout.
+    pub fn output(mut self, output: impl Write + 'static) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// Where the `readLine` native reads from. Defaults to stdin.
+    pub fn input(mut self, input: impl BufRead + 'static) -> Self {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    /// Whether scripts can read and write files and import modules. Defaults
+    /// to true; when disabled those natives and `import` raise an error.
+    pub fn allow_filesystem(mut self, allow_filesystem: bool) -> Self {
+        self.allow_filesystem = allow_filesystem;
+        self
+    }
+
+    /// The command-line arguments the `args` native returns.
+    pub fn args(mut self, args: impl IntoIterator<Item = String>) -> Self {
+        self.args = args.into_iter().collect();
+        self
+    }
+
+    /// Where the debug features write bytecode listings, execution traces and
+    /// GC logs to. Defaults to stdout.
+    pub fn diagnostics(mut self, diagnostics: impl Write + 'static) -> Self {
+        self.diagnostics = Rc::new(RefCell::new(diagnostics));
+        self
+    }
+
+    /// Data owned by the embedding application that natives can reach through
+    /// [`NativeContext::host_data`].
+    pub fn host_data(mut self, data: impl Any) -> Self {
+        self.host_data = Some(Box::new(data));
+        self
+    }
+
+    /// How deep calls can nest before a "Stack overflow." error is raised.
+    pub fn max_frames(mut self, max_frames: usize) -> Self {
+        self.max_frames = max_frames;
+        self
+    }
+
+    /// How many values the stack can hold. The limit is checked when calling
+    /// a function, raising a "Stack overflow." error if it has been reached,
+    /// so it's a soft limit: a function's own locals and temporaries can take
+    /// the stack past it until the next call.
+    pub fn max_stack(mut self, max_stack: usize) -> Self {
+        self.max_stack = max_stack;
+        self
+    }
+
+    /// How deep calls from Rust into Lox code can nest before a "Stack
+    /// overflow." error is raised. These are the calls made by natives through
+    /// [`NativeContext::call`], `toString` methods and getters called by the
+    /// VM itself, and [`VM::call`]. Each one uses the thread's native stack,
+    /// so a VM running on a thread with a small stack may need a lower limit.
+    pub fn max_nested_calls(mut self, max_nested_calls: usize) -> Self {
+        self.max_nested_calls = max_nested_calls;
+        self
+    }
+
+    /// Called with each warning the compiler finds in a script, before the
+    /// script runs. Warnings are ignored by default.
+    pub fn on_warning(mut self, on_warning: impl FnMut(&CompileWarning) + 'static) -> Self {
+        self.on_warning = Some(Box::new(on_warning));
+        self
+    }
+
+    /// The directory `import` paths in the main script are relative to.
+    /// Defaults to the current directory. Imports in a module are always
+    /// relative to the module's own directory.
+    pub fn import_root(mut self, import_root: impl Into<PathBuf>) -> Self {
+        self.import_root = import_root.into();
+        self
+    }
+
+    pub fn build(self) -> VM {
+        VM::with_builder(self)
+    }
+}
+
+impl Default for VMBuilder {
+    fn default() -> Self {
+        Self {
+            output: Box::new(io::stdout()),
+            diagnostics: Rc::new(RefCell::new(io::stdout())),
+            host_data: None,
+            input: None,
+            allow_filesystem: true,
+            args: Vec::new(),
+            max_frames: FRAME_MAX,
+            max_stack: STACK_MAX,
+            max_nested_calls: NESTED_CALL_MAX,
+            on_warning: None,
+            import_root: PathBuf::new(),
+        }
+    }
+}
+
+/// What a native function gets to interact with the VM calling it.
+///
+/// Values allocated through the context stay alive until the native returns,
+/// even if they aren't reachable from the script yet.
+pub struct NativeContext<'vm> {
+    vm: &'vm mut VM,
+}
+
+impl<'vm> NativeContext<'vm> {
+    pub fn gc(&self) -> &Gc {
+        &self.vm.gc
+    }
+
+    pub fn new_string(&mut self, string: impl Into<String>) -> Value {
+        let string = self.vm.intern(string.into());
+        self.root(Value::String(string))
+    }
+
+    pub fn new_list(&mut self, items: Vec<Value>) -> Value {
+        let list = self.vm.alloc(List::new(items));
+        self.root(Value::List(list))
+    }
+
+    /// Creates a map from key and value pairs, failing if a key can't be used
+    /// as a map key.
+    pub fn new_map(&mut self, entries: Vec<(Value, Value)>) -> Result<Value, RuntimeError> {
+        let mut map = Map::new();
+        for (key, value) in entries {
+            map.insert(self.vm.map_key(key)?, value);
+        }
+
+        let map = self.vm.alloc(map);
+        Ok(self.root(Value::Map(map)))
+    }
+
+    /// Creates a native function, which can keep state like an open file
+    /// between calls.
+    pub fn new_native<F>(&mut self, name: &str, arity: impl Into<Arity>, function: F) -> Value
+    where
+        F: Fn(&mut NativeContext, &[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        let name = self.vm.intern(name.to_owned());
+        self.root(Value::String(name));
+        let native = NativeFunction::new(name, arity.into(), Rc::new(function));
+        let native = self.vm.alloc(native);
+        self.root(Value::NativeFunction(native))
+    }
+
+    /// Reads a line from the VM's input without its line ending, or `None`
+    /// at the end of the input.
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        let read = match self.vm.input.as_mut() {
+            Some(input) => input.read_line(&mut line)?,
+            None => io::stdin().read_line(&mut line)?,
+        };
+
+        if read == 0 {
+            return Ok(None);
+        }
+
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+
+    /// The command-line arguments passed to the script, see [`VMBuilder::args`].
+    pub fn args(&self) -> &[String] {
+        &self.vm.args
+    }
+
+    /// Builds the error that stops the script with an exit code, which
+    /// `catch` blocks can't handle. It's returned as an
+    /// [`InterpretError::Exit`] instead of a runtime error.
+    pub fn exit(&self, code: i32) -> RuntimeError {
+        RuntimeError {
+            exit_code: Some(code),
+            ..self.vm.runtime_error("Exit.")
+        }
+    }
+
+    /// Whether the embedding application lets scripts use the filesystem, see
+    /// [`VMBuilder::allow_filesystem`].
+    pub fn allows_filesystem(&self) -> bool {
+        self.vm.allow_filesystem
+    }
+
+    /// Builds a runtime error pointing at the Lox code that called the native.
+    pub fn error(&self, message: &str) -> RuntimeError {
+        self.vm.runtime_error(message)
+    }
+
+    /// Calls back into Lox code, see [`VM::call`].
+    pub fn call(&mut self, callee: Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        let value = self.vm.reenter(callee, args)?;
+        Ok(self.root(value))
+    }
+
+    /// Converts a value to a string, see [`VM::stringify`].
+    pub fn stringify(&mut self, value: Value) -> Result<String, RuntimeError> {
+        self.vm.stringify(value)
+    }
+
+    /// Creates an `Error` instance with the current call stack, the same way
+    /// the `Error` native does.
+    pub fn new_error(&mut self, message: Value) -> Value {
+        let stack = self.vm.runtime_error("").stack;
+        let error = self.vm.error_instance(message, &stack);
+        self.root(error)
+    }
+
+    pub fn host_data<T: 'static>(&mut self) -> Option<&mut T> {
+        self.vm.host_data.as_mut()?.downcast_mut()
+    }
+
+    fn root(&mut self, value: Value) -> Value {
+        self.vm.native_roots.push(value);
+        value
+    }
+}
+
+/// The names of the methods an instance can define to overload operators.
+/// The reflected ones are called on the right-hand operand when the left one
+/// isn't an instance.
+struct Operators {
+    add: GcRef<String>,
+    subtract: GcRef<String>,
+    multiply: GcRef<String>,
+    divide: GcRef<String>,
+    reflected_add: GcRef<String>,
+    reflected_subtract: GcRef<String>,
+    reflected_multiply: GcRef<String>,
+    reflected_divide: GcRef<String>,
+    less: GcRef<String>,
+    greater: GcRef<String>,
+    equal: GcRef<String>,
+    negate: GcRef<String>,
+    index: GcRef<String>,
+}
+
+impl Operators {
+    fn new(gc: &mut Gc) -> Self {
+        Self {
+            add: gc.intern("__add".to_owned()),
+            subtract: gc.intern("__sub".to_owned()),
+            multiply: gc.intern("__mul".to_owned()),
+            divide: gc.intern("__div".to_owned()),
+            reflected_add: gc.intern("__radd".to_owned()),
+            reflected_subtract: gc.intern("__rsub".to_owned()),
+            reflected_multiply: gc.intern("__rmul".to_owned()),
+            reflected_divide: gc.intern("__rdiv".to_owned()),
+            less: gc.intern("__lt".to_owned()),
+            greater: gc.intern("__gt".to_owned()),
+            equal: gc.intern("__eq".to_owned()),
+            negate: gc.intern("__neg".to_owned()),
+            index: gc.intern("__index".to_owned()),
+        }
+    }
+
+    fn names(&self) -> [GcRef<String>; 13] {
+        [
+            self.add,
+            self.subtract,
+            self.multiply,
+            self.divide,
+            self.reflected_add,
+            self.reflected_subtract,
+            self.reflected_multiply,
+            self.reflected_divide,
+            self.less,
+            self.greater,
+            self.equal,
+            self.negate,
+            self.index,
+        ]
+    }
 }
 
 #[derive(Clone)]
@@ -39,14 +451,35 @@ impl CallFrame {
     }
 }
 
-pub enum InterpretResult {
-    Ok,
-    CompileError,
-    RuntimeError,
-}
-
 macro_rules! binary_op {
-    ($self:ident, +) => {{
+    ($self:ident, +, $operator:ident, $reflected:ident) => {{
+        match ($self.peek(1), $self.peek(0)) {
+            (Value::Instance(instance), b)
+                if !matches!(b, Value::String(_))
+                    || $self.operator_method(instance, $self.operators.$operator).is_some() =>
+            {
+                $self.call_operator(instance, $self.operators.$operator, 1)?;
+                continue;
+            }
+            (a, Value::Instance(instance))
+                if !matches!(a, Value::String(_))
+                    || $self.operator_method(instance, $self.operators.$reflected).is_some() =>
+            {
+                $self.call_reflected_operator(instance, $self.operators.$reflected)?;
+                continue;
+            }
+            (a @ Value::Instance(_), b @ Value::String(_))
+            | (a @ Value::String(_), b @ Value::Instance(_)) => {
+                let result = $self.stringify(a)? + &$self.stringify(b)?;
+                let result = $self.intern(result);
+                $self.pop();
+                $self.pop();
+                $self.push(Value::String(result));
+                continue;
+            }
+            _ => (),
+        }
+
         let b = $self.pop();
         let a = $self.pop();
 
@@ -60,22 +493,32 @@ macro_rules! binary_op {
                 Value::String(result)
             }
             _ => {
-                $self.runtime_error("Operands must be two numbers or two strings.");
-                return InterpretResult::RuntimeError;
+                return Err($self.runtime_error("Operands must be two numbers or two strings."));
             }
         };
 
         $self.push(value);
     }};
-    ($self:ident, $op:tt) => {{
+    ($self:ident, $op:tt, $operator:ident, $reflected:ident) => {{
+        match ($self.peek(1), $self.peek(0)) {
+            (Value::Instance(instance), _) => {
+                $self.call_operator(instance, $self.operators.$operator, 1)?;
+                continue;
+            }
+            (_, Value::Instance(instance)) => {
+                $self.call_reflected_operator(instance, $self.operators.$reflected)?;
+                continue;
+            }
+            _ => (),
+        }
+
         let b = $self.pop();
         let a = $self.pop();
 
         let value = match (a, b) {
             (Value::Number(a), Value::Number(b)) => (a $op b).into(),
             _ => {
-                $self.runtime_error("Operands must be numbers.");
-                return InterpretResult::RuntimeError;
+                return Err($self.runtime_error("Operands must be numbers."));
             }
         };
 
@@ -85,22 +528,127 @@ macro_rules! binary_op {
 
 impl VM {
     pub fn new() -> Self {
+        Self::builder().build()
+    }
+
+    pub fn builder() -> VMBuilder {
+        VMBuilder::default()
+    }
+
+    fn with_builder(builder: VMBuilder) -> Self {
         let mut gc = Gc::new();
+        #[cfg(feature = "debug_log_gc")]
+        gc.set_log(builder.diagnostics.clone());
         let init_string = gc.intern("init".to_string());
+        let to_string_string = gc.intern("toString".to_string());
+        let operators = Operators::new(&mut gc);
+        let error_name = gc.intern("Error".to_string());
+        let error_class = gc.alloc(Class::new(error_name));
 
         let mut vm = Self {
             gc,
-            frames: Vec::with_capacity(FRAME_MAX),
-            stack: Vec::with_capacity(STACK_MAX),
+            frames: Vec::new(),
+            stack: Vec::new(),
             globals: Table::new(),
+            builtins: Table::new(),
+            modules: HashMap::new(),
+            import_root: builder.import_root,
             open_upvalues: Vec::new(),
             init_string,
+            to_string_string,
+            operators,
+            error_class,
+            output: builder.output,
+            diagnostics: builder.diagnostics,
+            host_data: builder.host_data,
+            input: builder.input,
+            allow_filesystem: builder.allow_filesystem,
+            args: builder.args,
+            native_roots: Vec::new(),
+            pinned: Rc::default(),
+            max_frames: builder.max_frames,
+            max_stack: builder.max_stack,
+            max_nested_calls: builder.max_nested_calls,
+            nested_calls: 0,
+            on_warning: builder.on_warning,
         };
 
-        vm.define_native("clock", 0, Native(clock_native));
+        vm.register_native("clock", 0, clock_native);
+        vm.register_native("Error", 1, error_native);
+        vm.register_native("len", 1, len_native);
+        vm.register_native("keys", 1, keys_native);
+        vm.register_native("values", 1, values_native);
+        vm.register_native("str", 1, str_native);
+
+        for (name, function) in MATH_FUNCTIONS {
+            vm.register_native(name, 1, move |ctx, args| {
+                Ok(function(number_arg(ctx, name, args[0])?).into())
+            });
+        }
+        vm.register_native("pow", 2, pow_native);
+        vm.register_native("atan2", 2, atan2_native);
+        vm.register_native("min", Arity::AtLeast(1), min_native);
+        vm.register_native("max", Arity::AtLeast(1), max_native);
+        vm.define_builtin("PI", f64::consts::PI.into());
+        vm.define_builtin("E", f64::consts::E.into());
+        vm.define_builtin("INF", f64::INFINITY.into());
+        vm.define_builtin("NAN", f64::NAN.into());
+
+        vm.register_native("substring", Arity::Between(2, 3), substring_native);
+        vm.register_native("indexOf", 2, index_of_native);
+        vm.register_native("contains", 2, contains_native);
+        vm.register_native("startsWith", 2, starts_with_native);
+        vm.register_native("endsWith", 2, ends_with_native);
+        vm.register_native("split", 2, split_native);
+        vm.register_native("join", 2, join_native);
+        vm.register_native("trim", 1, trim_native);
+        vm.register_native("upper", 1, upper_native);
+        vm.register_native("lower", 1, lower_native);
+        vm.register_native("replace", 3, replace_native);
+        vm.register_native("repeat", 2, repeat_native);
+        vm.register_native("chr", 1, chr_native);
+        vm.register_native("ord", 1, ord_native);
+
+        vm.register_native("readFile", 1, read_file_native);
+        vm.register_native("readLines", 1, read_lines_native);
+        vm.register_native("writeFile", 2, write_file_native);
+        vm.register_native("appendFile", 2, append_file_native);
+        vm.register_native("exists", 1, exists_native);
+        vm.register_native("listDir", 1, list_dir_native);
+        vm.register_native("readLine", 0, read_line_native);
+        vm.register_native("args", 0, args_native);
+        vm.register_native("env", 1, env_native);
+        vm.register_native("exit", Arity::Between(0, 1), exit_native);
+        vm.register_native("json_parse", 1, json_parse_native);
+        vm.register_native(
+            "json_stringify",
+            Arity::Between(1, 2),
+            json_stringify_native,
+        );
         vm
     }
 
+    /// Defines a global function implemented in Rust. The VM checks the
+    /// number of arguments against `arity` before calling `function`.
+    pub fn register_native<F>(&mut self, name: &str, arity: impl Into<Arity>, function: F)
+    where
+        F: Fn(&mut NativeContext, &[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        let name = self.intern(name.to_owned());
+        self.push(Value::String(name));
+        let native = NativeFunction::new(name, arity.into(), Rc::new(function));
+        let native = self.alloc(native);
+        self.pop();
+
+        self.builtins.insert(name, Value::NativeFunction(native));
+    }
+
+    /// Defines a constant visible from every module, like `PI`.
+    fn define_builtin(&mut self, name: &str, value: Value) {
+        let name = self.intern(name.to_owned());
+        self.builtins.insert(name, value);
+    }
+
     fn read_byte(&mut self) -> OpCode {
         self.current_frame_mut().ip += 1;
         self.current_chunk().code[self.current_frame().ip - 1]
@@ -119,13 +667,13 @@ impl VM {
     fn mark_and_sweep(&mut self) {
         if self.gc.should_gc() {
             #[cfg(feature = "debug_log_gc")]
-            println!("-- gc begin");
+            let _ = writeln!(self.diagnostics.borrow_mut(), "-- gc begin");
 
             self.mark_roots();
             self.gc.collect_garbage();
 
             #[cfg(feature = "debug_log_gc")]
-            println!("-- gc end");
+            let _ = writeln!(self.diagnostics.borrow_mut(), "-- gc end");
         }
     }
 
@@ -142,8 +690,26 @@ impl VM {
             self.gc.mark_object(upvalue);
         }
 
+        for &value in &self.native_roots {
+            self.gc.mark_value(value);
+        }
+
+        for &value in self.pinned.borrow().slots.iter().flatten() {
+            self.gc.mark_value(value);
+        }
+
+        for &module in self.modules.values() {
+            self.gc.mark_object(module);
+        }
+
         self.gc.mark_table(&self.globals);
+        self.gc.mark_table(&self.builtins);
         self.gc.mark_object(self.init_string);
+        self.gc.mark_object(self.to_string_string);
+        for name in self.operators.names() {
+            self.gc.mark_object(name);
+        }
+        self.gc.mark_object(self.error_class);
     }
 
     fn push(&mut self, value: Value) {
@@ -158,36 +724,38 @@ impl VM {
         self.stack[self.stack.len() - distance - 1]
     }
 
-    fn call(&mut self, closure_ref: GcRef<Closure>, arg_count: usize) -> bool {
+    fn call_closure(
+        &mut self,
+        closure_ref: GcRef<Closure>,
+        arg_count: usize,
+    ) -> Result<(), RuntimeError> {
         let closure = self.gc.deref(closure_ref);
         let function = self.gc.deref(closure.function);
 
         if arg_count != function.arity {
-            self.runtime_error(&format!(
+            return Err(self.runtime_error(&format!(
                 "Expected {} arguments but got {}.",
                 function.arity, arg_count
-            ));
-            return false;
+            )));
         }
 
-        if self.frames.len() == FRAME_MAX {
-            self.runtime_error("Stack overflow.");
-            return false;
+        if self.frames.len() >= self.max_frames || self.stack.len() > self.max_stack {
+            return Err(self.runtime_error("Stack overflow."));
         }
 
         let frame = CallFrame::new(closure_ref, self.stack.len() - arg_count - 1);
         self.frames.push(frame);
 
-        true
+        Ok(())
     }
 
-    fn call_value(&mut self, callee: Value, arg_count: usize) -> bool {
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<(), RuntimeError> {
         match callee {
             Value::BoundMethod(bound_ref) => {
                 let bound = self.gc.deref(bound_ref);
                 let slot = self.stack.len() - arg_count - 1;
                 self.stack[slot] = bound.receiver;
-                self.call(bound.method, arg_count)
+                self.call_closure(bound.method, arg_count)
             }
             Value::Class(class) => {
                 let instance = Instance::new(class);
@@ -197,25 +765,36 @@ impl VM {
 
                 let class = self.gc.deref(class);
                 if let Some(Value::Closure(init)) = class.methods.get(&self.init_string) {
-                    return self.call(*init, arg_count);
+                    return self.call_closure(*init, arg_count);
                 } else if arg_count != 0 {
-                    self.runtime_error(&format!("Expected 0 arguments but got {}.", arg_count));
-                    return false;
+                    return Err(
+                        self.runtime_error(&format!("Expected 0 arguments but got {}.", arg_count))
+                    );
                 }
-                true
+                Ok(())
             }
-            Value::Closure(closure) => self.call(closure, arg_count),
-            Value::NativeFunction(function) => {
+            Value::Closure(closure) => self.call_closure(closure, arg_count),
+            Value::NativeFunction(native) => {
+                let native = self.gc.deref(native);
+                if !native.arity.accepts(arg_count) {
+                    return Err(self.runtime_error(&format!(
+                        "Expected {} arguments but got {}.",
+                        native.arity, arg_count
+                    )));
+                }
+
+                let function = native.function.clone();
                 let offset = self.stack.len() - arg_count;
-                let value = function.0(arg_count, &self.stack[offset..]);
+                let args = self.stack[offset..].to_vec();
+                let roots = self.native_roots.len();
+                let result = function(&mut NativeContext { vm: self }, &args);
+                self.native_roots.truncate(roots);
+
                 self.stack.truncate(offset - 1);
-                self.push(value);
-                true
-            }
-            _ => {
-                self.runtime_error("Can only call functions and classes.");
-                false
+                self.push(result?);
+                Ok(())
             }
+            _ => Err(self.runtime_error("Can only call functions and classes.")),
         }
     }
 
@@ -224,46 +803,166 @@ impl VM {
         class: GcRef<Class>,
         name: GcRef<String>,
         arg_count: usize,
-    ) -> bool {
+    ) -> Result<(), RuntimeError> {
         let class = self.gc.deref(class);
-        if let Some(Value::Closure(method)) = class.methods.get(&name) {
-            return self.call(*method, arg_count);
+        let methods = Self::methods_for(class, self.peek(arg_count));
+        if let Some(Value::Closure(method)) = methods.get(&name) {
+            return self.call_closure(*method, arg_count);
         }
 
         let name = self.gc.deref(name);
-        self.runtime_error(&format!("Undefined property '{}'.", name));
-        false
+        Err(self.runtime_error(&format!("Undefined property '{}'.", name)))
     }
 
-    fn invoke(&mut self, name: GcRef<String>, arg_count: usize) -> bool {
+    fn invoke(&mut self, name: GcRef<String>, arg_count: usize) -> Result<(), RuntimeError> {
+        if let Value::Module(module) = self.peek(arg_count) {
+            let value = self.module_member(module, name)?;
+            let slot = self.stack.len() - arg_count - 1;
+            self.stack[slot] = value;
+            return self.call_value(value, arg_count);
+        }
+
+        if let Value::Class(class) = self.peek(arg_count) {
+            if !self.gc.deref(class).statics.contains_key(&name) {
+                return Err(self.runtime_error("Only instances have methods."));
+            }
+            return self.invoke_from_class(class, name, arg_count);
+        }
+
         if let Value::Instance(instance) = self.peek(arg_count) {
             let instance = self.gc.deref(instance);
-            if let Some(&value) = instance.fields.get(&name) {
-                let slot = self.stack.len() - arg_count - 1;
-                self.stack[slot] = value;
-                return self.call_value(value, arg_count);
-            }
+            let class = instance.class;
+            let value = match instance.fields.get(&name) {
+                Some(&value) => value,
+                None => match self.gc.deref(class).getters.get(&name) {
+                    Some(&Value::Closure(getter)) => {
+                        let getter = BoundMethod::new(self.peek(arg_count), getter);
+                        let getter = self.alloc(getter);
+                        self.reenter(Value::BoundMethod(getter), &[])?
+                    }
+                    _ => return self.invoke_from_class(class, name, arg_count),
+                },
+            };
 
-            return self.invoke_from_class(instance.class, name, arg_count);
+            let slot = self.stack.len() - arg_count - 1;
+            self.stack[slot] = value;
+            return self.call_value(value, arg_count);
         }
 
-        self.runtime_error("Only instances have methods.");
-        false
+        Err(self.runtime_error("Only instances have methods."))
     }
 
-    fn bind_method(&mut self, class: GcRef<Class>, name: GcRef<String>) -> bool {
+    fn module_member(
+        &self,
+        module: GcRef<Module>,
+        name: GcRef<String>,
+    ) -> Result<Value, RuntimeError> {
+        let module = self.gc.deref(module);
+        match module.globals.get(&name) {
+            Some(&value) => Ok(value),
+            None => Err(self.runtime_error(&format!(
+                "Undefined variable '{}' in module '{}'.",
+                self.gc.deref(name),
+                self.gc.deref(module.name)
+            ))),
+        }
+    }
+
+    fn bind_method(
+        &mut self,
+        class: GcRef<Class>,
+        name: GcRef<String>,
+    ) -> Result<(), RuntimeError> {
         let class = self.gc.deref(class);
-        if let Some(Value::Closure(method)) = class.methods.get(&name) {
+        let methods = Self::methods_for(class, self.peek(0));
+        if let Some(Value::Closure(method)) = methods.get(&name) {
             let bound = BoundMethod::new(self.peek(0), *method);
             let bound = self.alloc(bound);
             self.pop();
             self.push(Value::BoundMethod(bound));
-            return true;
+            return Ok(());
         }
 
         let name = self.gc.deref(name);
-        self.runtime_error(&format!("Undefined property '{}'.", name));
-        false
+        Err(self.runtime_error(&format!("Undefined property '{}'.", name)))
+    }
+
+    /// The method overloading an operator for `instance`, if its class
+    /// defines one.
+    fn operator_method(
+        &self,
+        instance: GcRef<Instance>,
+        name: GcRef<String>,
+    ) -> Option<GcRef<Closure>> {
+        let class = self.gc.deref(self.gc.deref(instance).class);
+        match class.methods.get(&name) {
+            Some(&Value::Closure(method)) => Some(method),
+            _ => None,
+        }
+    }
+
+    /// Calls the method overloading an operator on `instance`, which is below
+    /// the other `arg_count` operands on the stack.
+    fn call_operator(
+        &mut self,
+        instance: GcRef<Instance>,
+        name: GcRef<String>,
+        arg_count: usize,
+    ) -> Result<(), RuntimeError> {
+        match self.operator_method(instance, name) {
+            Some(method) => self.call_closure(method, arg_count),
+            None => {
+                let class = self.gc.deref(self.gc.deref(instance).class);
+                Err(self.runtime_error(&format!(
+                    "Class '{}' has no '{}' method.",
+                    self.gc.deref(class.name),
+                    self.gc.deref(name)
+                )))
+            }
+        }
+    }
+
+    /// Calls the method overloading an operator on `instance`, the right-hand
+    /// operand, with the left-hand operand as its argument.
+    fn call_reflected_operator(
+        &mut self,
+        instance: GcRef<Instance>,
+        name: GcRef<String>,
+    ) -> Result<(), RuntimeError> {
+        let top = self.stack.len() - 1;
+        self.stack.swap(top - 1, top);
+        self.call_operator(instance, name, 1)
+    }
+
+    /// The methods of `class` that can be called on `receiver`, which are its
+    /// static methods if the receiver is a class itself.
+    fn methods_for(class: &Class, receiver: Value) -> &Table {
+        match receiver {
+            Value::Class(_) => &class.statics,
+            _ => &class.methods,
+        }
+    }
+
+    fn list_index(&self, list: GcRef<List>, index: Value) -> Result<usize, RuntimeError> {
+        let index = match index {
+            Value::Number(index) => index,
+            _ => return Err(self.runtime_error("List index must be a number.")),
+        };
+
+        if index.fract() != 0.0 {
+            return Err(self.runtime_error("List index must be an integer."));
+        }
+
+        let list = self.gc.deref(list);
+        if index < 0.0 || index >= list.items.len() as f64 {
+            return Err(self.runtime_error("List index out of range."));
+        }
+
+        Ok(index as usize)
+    }
+
+    fn map_key(&self, key: Value) -> Result<MapKey, RuntimeError> {
+        MapKey::try_from(key).map_err(|message| self.runtime_error(message))
     }
 
     fn capture_upvalue(&mut self, location: usize) -> GcRef<Upvalue> {
@@ -296,44 +995,55 @@ impl VM {
         }
     }
 
-    fn define_method(&mut self, name: GcRef<String>) {
+    /// Adds the closure on top of the stack to the class below it, in the
+    /// table `methods` picks.
+    fn define_method(&mut self, name: GcRef<String>, methods: fn(&mut Class) -> &mut Table) {
         let method = self.peek(0);
         if let Value::Class(class) = self.peek(1) {
             let class = self.gc.deref_mut(class);
-            class.methods.insert(name, method);
+            methods(class).insert(name, method);
             self.pop();
         }
     }
 
-    fn reset_stack(&mut self) {
-        self.stack.clear();
-        self.frames.clear();
-        self.open_upvalues.clear();
-    }
-
-    fn runtime_error(&mut self, message: &str) {
-        eprintln!("{}", message);
+    fn runtime_error(&self, message: &str) -> RuntimeError {
+        let stack = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| {
+                let closure = self.gc.deref(frame.closure);
+                let function = self.gc.deref(closure.function);
+                let name = self.gc.deref(function.name);
+                let span = function.chunk.spans[frame.ip - 1];
+                Frame {
+                    function: if name.is_empty() {
+                        "script".to_owned()
+                    } else {
+                        name.clone()
+                    },
+                    line: span.line,
+                    column: span.column,
+                }
+            })
+            .collect();
 
-        for frame in self.frames.iter().rev() {
+        let location = self.frames.last().map(|frame| {
             let closure = self.gc.deref(frame.closure);
-            let function = self.gc.deref(closure.function);
-            let index = frame.ip - 1;
-            let name = self.gc.deref(function.name);
-            eprint!("[line {}] in ", function.chunk.lines[index]);
-            if name.is_empty() {
-                eprintln!("script");
-            } else {
-                eprintln!("{}", name);
+            let chunk = &self.gc.deref(closure.function).chunk;
+            SourceSpan {
+                source: chunk.source.clone(),
+                span: chunk.spans[frame.ip - 1],
             }
+        });
+
+        RuntimeError {
+            message: message.to_owned(),
+            stack,
+            location,
+            thrown: None,
+            exit_code: None,
         }
-
-        self.reset_stack();
-    }
-
-    fn define_native(&mut self, name: &str, _arity: usize, native: Native) {
-        let name = self.gc.intern(name.to_owned());
-
-        self.globals.insert(name, Value::NativeFunction(native));
     }
 
     fn current_frame(&self) -> &CallFrame {
@@ -355,36 +1065,500 @@ impl VM {
         &function.chunk
     }
 
-    pub fn interpret(&mut self, source: &str) -> InterpretResult {
-        let function = compile(source, &mut self.gc);
-        if function.is_none() {
-            return InterpretResult::CompileError;
+    fn current_module(&self) -> Option<GcRef<Module>> {
+        let closure = self.current_closure();
+        self.gc.deref(closure.function).module
+    }
+
+    /// The globals of the module the running function was defined in.
+    fn current_globals(&self) -> &Table {
+        match self.current_module() {
+            Some(module) => &self.gc.deref(module).globals,
+            None => &self.globals,
+        }
+    }
+
+    fn current_globals_mut(&mut self) -> &mut Table {
+        match self.current_module() {
+            Some(module) => &mut self.gc.deref_mut(module).globals,
+            None => &mut self.globals,
         }
-        let function = function.unwrap();
+    }
+
+    #[cfg(feature = "debug_print_code")]
+    fn disassemble_function(&self, function: GcRef<Function>) {
+        let function = self.gc.deref(function);
+        for &constant in &function.chunk.constants {
+            if let Value::Closure(closure) = constant {
+                self.disassemble_function(self.gc.deref(closure).function);
+            }
+        }
+
+        let mut diagnostics = self.diagnostics.borrow_mut();
+        let mut disassembler = Disassembler::new(&self.gc, &function.chunk, &mut *diagnostics);
+        let _ = disassembler.disassemble_chunk(function.name);
+    }
+
+    pub fn interpret(&mut self, source: &str) -> Result<(), InterpretError> {
+        let function = self.compile(source)?;
+        self.run_script(function)
+    }
+
+    /// Compiles a script without running it, returning bytecode that
+    /// [`VM::load_bytecode`] can run later.
+    pub fn compile_bytecode(&mut self, source: &str) -> Result<Vec<u8>, InterpretError> {
+        let function = self.compile(source)?;
+        Ok(bytecode::serialize(self.gc.deref(function), &self.gc))
+    }
+
+    /// Runs a script compiled by [`VM::compile_bytecode`]. The bytecode is
+    /// checked before anything runs, so a corrupted or incompatible file is
+    /// reported as an [`InterpretError::Bytecode`] error.
+    pub fn load_bytecode(&mut self, bytes: &[u8]) -> Result<(), InterpretError> {
+        let function =
+            bytecode::deserialize(bytes, &mut self.gc).map_err(InterpretError::Bytecode)?;
+        self.run_script(function)
+    }
+
+    fn compile(&mut self, source: &str) -> Result<GcRef<Function>, InterpretError> {
+        let (result, warnings) = compile(source, &mut self.gc, &self.globals, None);
+        if let Some(on_warning) = self.on_warning.as_mut() {
+            warnings.iter().for_each(on_warning);
+        }
+
+        result.map_err(InterpretError::Compile)
+    }
+
+    /// Loads the module at `path`, relative to the directory of the module
+    /// running the import. Each module only runs the first time it's
+    /// imported. A module that is imported again while it is still running,
+    /// because of a circular import, is returned as it is so far.
+    fn import(&mut self, path: GcRef<String>) -> Result<GcRef<Module>, RuntimeError> {
+        if !self.allow_filesystem {
+            return Err(self.runtime_error("Filesystem access is disabled."));
+        }
+
+        let base = match self.current_module() {
+            Some(module) => self.gc.deref(module).path.parent().unwrap_or(Path::new("")),
+            None => self.import_root.as_path(),
+        };
+        let relative = self.gc.deref(path);
+        let path = match fs::canonicalize(base.join(relative)) {
+            Ok(path) => path,
+            Err(_) => {
+                let message = format!("Could not find module '{}'.", relative);
+                return Err(self.runtime_error(&message));
+            }
+        };
+
+        if let Some(&module) = self.modules.get(&path) {
+            return Ok(module);
+        }
+
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(_) => {
+                let message = format!("Could not read module '{}'.", relative);
+                return Err(self.runtime_error(&message));
+            }
+        };
+
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = self.intern(name.into_owned());
+        self.push(Value::String(name));
+        let module = self.alloc(Module::new(name, path.clone()));
+        self.pop();
+        self.modules.insert(path.clone(), module);
+
+        let (result, warnings) = compile(&source, &mut self.gc, &Table::new(), Some(module));
+        if let Some(on_warning) = self.on_warning.as_mut() {
+            warnings.iter().for_each(on_warning);
+        }
+
+        let result = match result {
+            Ok(function) => {
+                // Nothing can be collected before the call roots the closure.
+                let closure = self.gc.alloc(Closure::new(function));
+                self.reenter(Value::Closure(closure), &[])
+            }
+            Err(errors) => {
+                let name = self.gc.deref(name);
+                let mut message = format!("Could not compile module '{}':", name);
+                for error in errors {
+                    message.push_str(&format!("\n{}", error));
+                }
+
+                Err(self.runtime_error(&message))
+            }
+        };
+
+        if let Err(error) = result {
+            // Let a later import try again rather than see a broken module.
+            self.modules.remove(&path);
+            return Err(error);
+        }
+
+        Ok(module)
+    }
+
+    fn run_script(&mut self, function: GcRef<Function>) -> Result<(), InterpretError> {
+        #[cfg(feature = "debug_print_code")]
+        self.disassemble_function(function);
+
+        // The new function isn't reachable from any root yet, so it must not be
+        // collected while its closure is being allocated.
         let closure = Closure::new(function);
-        let closure = self.alloc(closure);
+        let closure = self.gc.alloc(closure);
+
+        match self.reenter(Value::Closure(closure), &[]) {
+            Ok(_) => Ok(()),
+            Err(RuntimeError {
+                exit_code: Some(code),
+                ..
+            }) => Err(InterpretError::Exit(code)),
+            Err(error) => Err(InterpretError::Runtime(self.uncaught(error))),
+        }
+    }
 
-        self.push(Value::Closure(closure));
-        self.frames.push(CallFrame::new(closure, 0));
+    /// Calls a closure, bound method, class or native with the given arguments
+    /// and returns its result. Globals defined by earlier calls or scripts are
+    /// visible, and if the call fails the VM is left as it was before it.
+    ///
+    /// The result stays alive until the returned [`Pinned`] is dropped.
+    pub fn call(&mut self, callee: Value, args: &[Value]) -> Result<Pinned, RuntimeError> {
+        match self.reenter(callee, args) {
+            Ok(value) => Ok(self.pin(value)),
+            Err(error) => Err(self.uncaught(error)),
+        }
+    }
 
-        self.run()
+    /// Keeps `value` alive across collections until the returned [`Pinned`] is
+    /// dropped, for values the host holds on to between calls.
+    pub fn pin(&mut self, value: Value) -> Pinned {
+        let slot = match value {
+            Value::Nil | Value::Bool(_) | Value::Number(_) => None,
+            _ => Some(self.pinned.borrow_mut().insert(value)),
+        };
+        Pinned {
+            value,
+            slot,
+            pins: Rc::clone(&self.pinned),
+        }
+    }
+
+    /// Calls `callee` and runs it to completion in a nested run loop. The
+    /// result is only reachable from the caller.
+    fn reenter(&mut self, callee: Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        if self.nested_calls >= self.max_nested_calls {
+            return Err(self.runtime_error("Stack overflow."));
+        }
+
+        self.nested_calls += 1;
+        let result = self.run_nested(callee, args);
+        self.nested_calls -= 1;
+        result
     }
 
-    fn run(&mut self) -> InterpretResult {
+    fn run_nested(&mut self, callee: Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        let depth = self.frames.len();
+        let slot = self.stack.len();
+        self.push(callee);
+        self.stack.extend_from_slice(args);
+
+        let result = self.call_value(callee, args.len()).and_then(|()| {
+            if self.frames.len() > depth {
+                self.run(depth)
+            } else {
+                Ok(())
+            }
+        });
+
+        match result {
+            Ok(()) => Ok(self.pop()),
+            Err(error) => {
+                self.close_upvalues(slot);
+                self.frames.truncate(depth);
+                self.stack.truncate(slot);
+                Err(error)
+            }
+        }
+    }
+
+    /// Converts a value to a string the way `print` does, calling the
+    /// `toString` method of instances that define one.
+    pub fn stringify(&mut self, value: Value) -> Result<String, RuntimeError> {
+        self.write_string(value, true)
+    }
+
+    /// Writes `value` as [`VM::stringify`] does. Without `call_to_string`,
+    /// instances are written by their class instead, and writing can't fail.
+    fn write_string(&mut self, value: Value, call_to_string: bool) -> Result<String, RuntimeError> {
+        let mut string = String::new();
+        // The lists and maps being written, innermost last, with the position
+        // of the next item in each. Keeping them here instead of recursing
+        // means deeply nested collections can't overflow the native stack.
+        // One that's already open contains itself, and is written as `[...]`
+        // or `{...}` instead of recursing forever. Open ones are rooted, since
+        // a `toString` method can remove them from whatever held them.
+        let roots = self.native_roots.len();
+        let mut open = Vec::new();
+        let mut open_lists = HashSet::new();
+        let mut open_maps = HashSet::new();
+        let mut next = Some(value);
+
+        loop {
+            match next.take() {
+                Some(Value::List(list)) if open_lists.insert(list) => {
+                    string.push('[');
+                    open.push((Value::List(list), 0));
+                    self.native_roots.push(Value::List(list));
+                }
+                Some(Value::Map(map)) if open_maps.insert(map) => {
+                    string.push('{');
+                    open.push((Value::Map(map), 0));
+                    self.native_roots.push(Value::Map(map));
+                }
+                Some(Value::List(_)) => string += "[...]",
+                Some(Value::Map(_)) => string += "{...}",
+                Some(value) => {
+                    if let Err(error) = self.write_value(&mut string, value, call_to_string) {
+                        self.native_roots.truncate(roots);
+                        return Err(error);
+                    }
+                }
+                None => (),
+            }
+
+            let Some(&(collection, i)) = open.last() else {
+                return Ok(string);
+            };
+            open.last_mut().unwrap().1 += 1;
+
+            next = match collection {
+                Value::List(list) => self.gc.deref(list).items.get(i).map(|&item| {
+                    if i > 0 {
+                        string += ", ";
+                    }
+                    item
+                }),
+                Value::Map(map) => self.gc.deref(map).entries.get(i / 2).map(|&(key, item)| {
+                    if i % 2 == 1 {
+                        string += ": ";
+                        item
+                    } else {
+                        if i > 0 {
+                            string += ", ";
+                        }
+                        key
+                    }
+                }),
+                _ => unreachable!("Only lists and maps are opened"),
+            };
+
+            if next.is_none() {
+                match collection {
+                    Value::List(list) => {
+                        string.push(']');
+                        open_lists.remove(&list);
+                    }
+                    Value::Map(map) => {
+                        string.push('}');
+                        open_maps.remove(&map);
+                    }
+                    _ => unreachable!("Only lists and maps are opened"),
+                }
+                open.pop();
+                self.native_roots.pop();
+            }
+        }
+    }
+
+    /// Appends a value that isn't a list or map to `string`.
+    fn write_value(
+        &mut self,
+        string: &mut String,
+        value: Value,
+        call_to_string: bool,
+    ) -> Result<(), RuntimeError> {
+        let (Value::Instance(instance), true) = (value, call_to_string) else {
+            *string += &GcTraceFormatter::new(value, &self.gc).to_string();
+            return Ok(());
+        };
+
+        let class = self.gc.deref(self.gc.deref(instance).class);
+        let Some(&Value::Closure(method)) = class.methods.get(&self.to_string_string) else {
+            *string += &GcTraceFormatter::new(value, &self.gc).to_string();
+            return Ok(());
+        };
+
+        let method = self.alloc(BoundMethod::new(value, method));
+        match self.reenter(Value::BoundMethod(method), &[])? {
+            Value::String(result) => {
+                let result: &String = self.gc.deref(result);
+                string.push_str(result);
+                Ok(())
+            }
+            _ => Err(self.runtime_error("toString() must return a string.")),
+        }
+    }
+
+    /// Looks up a global variable defined by a script or a native.
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        let name = self.gc.find_string(name)?;
+        self.globals
+            .get(&name)
+            .or_else(|| self.builtins.get(&name))
+            .copied()
+    }
+
+    /// Creates a string value to pass to [`VM::call`]. Creating it never
+    /// triggers a collection, so values already held by the caller stay valid.
+    pub fn new_string(&mut self, string: impl Into<String>) -> Value {
+        Value::String(self.gc.intern(string.into()))
+    }
+
+    pub fn gc(&self) -> &Gc {
+        &self.gc
+    }
+
+    /// Runs until the frame at `depth` returns, leaving its result on the stack.
+    /// Errors raised in a `try` block of one of the frames above `depth` are
+    /// handed to its handler instead of being returned.
+    fn run(&mut self, depth: usize) -> Result<(), RuntimeError> {
+        loop {
+            match self.execute(depth) {
+                Ok(()) => return Ok(()),
+                Err(error) => self.handle_error(error, depth)?,
+            }
+        }
+    }
+
+    /// Unwinds to the innermost handler covering the instruction that raised
+    /// `error` and jumps to it with the error value on the stack.
+    fn handle_error(&mut self, error: RuntimeError, depth: usize) -> Result<(), RuntimeError> {
+        if error.exit_code.is_some() {
+            return Err(error);
+        }
+
+        while self.frames.len() > depth {
+            let ip = self.current_frame().ip - 1;
+            let function = self.gc.deref(self.current_closure().function);
+            let handler = function
+                .handlers
+                .iter()
+                .find(|handler| handler.start <= ip && ip < handler.end)
+                .copied();
+
+            if let Some(handler) = handler {
+                let value = match error.thrown {
+                    Some(value) => value,
+                    None => {
+                        let message = self.intern(error.message);
+                        self.error_instance(Value::String(message), &error.stack)
+                    }
+                };
+
+                let slot = self.current_frame().slot + handler.depth;
+                self.close_upvalues(slot);
+                self.stack.truncate(slot);
+                self.push(value);
+                self.current_frame_mut().ip = handler.target;
+                return Ok(());
+            }
+
+            let slot = self.current_frame().slot;
+            self.close_upvalues(slot);
+            self.stack.truncate(slot);
+            self.frames.pop();
+        }
+
+        Err(error)
+    }
+
+    /// Creates an instance of `Error` with `message` and `stack` fields.
+    fn error_instance(&mut self, message: Value, stack: &[Frame]) -> Value {
+        let offset = self.stack.len();
+        self.push(message);
+        let instance = self.alloc(Instance::new(self.error_class));
+        self.push(Value::Instance(instance));
+
+        for frame in stack {
+            let frame = self.intern(frame.to_string());
+            self.push(Value::String(frame));
+        }
+        let frames = self.stack[offset + 2..].to_vec();
+        let frames = self.alloc(List::new(frames));
+        self.stack.truncate(offset + 2);
+        self.push(Value::List(frames));
+
+        let message_name = self.intern("message".to_owned());
+        let instance_mut = self.gc.deref_mut(instance);
+        instance_mut.fields.insert(message_name, message);
+        let stack_name = self.intern("stack".to_owned());
+        let instance_mut = self.gc.deref_mut(instance);
+        instance_mut.fields.insert(stack_name, Value::List(frames));
+
+        self.stack.truncate(offset);
+        Value::Instance(instance)
+    }
+
+    /// The error raised by `throw`. Its message is left empty until the error
+    /// reaches the host, see [`VM::uncaught`], since a caught one never needs it.
+    fn thrown_error(&self, value: Value) -> RuntimeError {
+        RuntimeError {
+            thrown: Some(value),
+            ..self.runtime_error("")
+        }
+    }
+
+    /// Fills in the message of an error thrown by a script that nothing
+    /// caught. Error instances are reported by their message, anything else as
+    /// printed.
+    fn uncaught(&mut self, mut error: RuntimeError) -> RuntimeError {
+        let Some(value) = error.thrown else {
+            return error;
+        };
+
+        let message =
+            match value {
+                Value::Instance(instance) => self.gc.find_string("message").and_then(|name| {
+                    match self.gc.deref(instance).fields.get(&name) {
+                        Some(&Value::String(message)) => Some(self.gc.deref(message).clone()),
+                        _ => None,
+                    }
+                }),
+                _ => None,
+            };
+        error.message = match message {
+            Some(message) => message,
+            None => self
+                .write_string(value, false)
+                .expect("Only toString() can fail"),
+        };
+        error
+    }
+
+    fn execute(&mut self, depth: usize) -> Result<(), RuntimeError> {
         use OpCode::*;
 
         loop {
             #[cfg(feature = "debug_trace_execution")]
             {
-                print!("          ");
+                let mut diagnostics = self.diagnostics.borrow_mut();
+                let _ = write!(diagnostics, "          ");
                 for &value in &self.stack {
-                    print!("[ {} ]", GcTraceFormatter::new(value, &self.gc))
+                    let _ = write!(
+                        diagnostics,
+                        "[ {} ]",
+                        GcTraceFormatter::new(value, &self.gc)
+                    );
                 }
-                println!();
+                let _ = writeln!(diagnostics);
 
                 let ip = self.current_frame().ip;
-                let disassembler = Disassembler::new(&self.gc, self.current_chunk());
-                disassembler.disassemble_instruction(ip);
+                let mut disassembler =
+                    Disassembler::new(&self.gc, self.current_chunk(), &mut *diagnostics);
+                let _ = disassembler.disassemble_instruction(ip);
             }
 
             let instruction = self.read_byte();
@@ -409,12 +1583,17 @@ impl VM {
                 }
                 OpGetGlobal(index) => {
                     let name = self.current_chunk().read_string(index);
-                    let value = match self.globals.get(&name) {
+                    let value = match self
+                        .current_globals()
+                        .get(&name)
+                        .or_else(|| self.builtins.get(&name))
+                    {
                         Some(&value) => value,
                         None => {
                             let name = self.gc.deref(name);
-                            self.runtime_error(&format!("Undefined variable '{}'.", name));
-                            return InterpretResult::RuntimeError;
+                            return Err(
+                                self.runtime_error(&format!("Undefined variable '{}'.", name))
+                            );
                         }
                     };
 
@@ -423,17 +1602,16 @@ impl VM {
                 OpDefineGlobal(index) => {
                     let name = self.current_chunk().read_string(index);
                     let value = self.pop();
-                    self.globals.insert(name, value);
+                    self.current_globals_mut().insert(name, value);
                 }
                 OpSetGlobal(index) => {
                     let name = self.current_chunk().read_string(index);
                     let value = self.peek(0);
-                    if let Entry::Occupied(mut e) = self.globals.entry(name) {
-                        e.insert(value);
+                    if let Some(global) = self.current_globals_mut().get_mut(&name) {
+                        *global = value;
                     } else {
                         let name = self.gc.deref(name);
-                        self.runtime_error(&format!("Undefined variable '{}'.", name));
-                        return InterpretResult::RuntimeError;
+                        return Err(self.runtime_error(&format!("Undefined variable '{}'.", name)));
                     }
                 }
                 OpGetUpvalue(slot) => {
@@ -469,7 +1647,18 @@ impl VM {
                     }
                 }
                 OpGetProperty(index) => {
-                    if let Value::Instance(instance) = self.peek(0) {
+                    if let Value::Module(module) = self.peek(0) {
+                        let name = self.current_chunk().read_string(index);
+                        let value = self.module_member(module, name)?;
+                        self.pop();
+                        self.push(value);
+                    } else if let Value::Class(class) = self.peek(0) {
+                        let name = self.current_chunk().read_string(index);
+                        if !self.gc.deref(class).statics.contains_key(&name) {
+                            return Err(self.runtime_error("Only instances have properties."));
+                        }
+                        self.bind_method(class, name)?;
+                    } else if let Value::Instance(instance) = self.peek(0) {
                         let name = self.current_chunk().read_string(index);
                         let instance = self.gc.deref(instance);
                         let class = instance.class;
@@ -479,65 +1668,95 @@ impl VM {
                             continue;
                         }
 
-                        if !self.bind_method(class, name) {
-                            return InterpretResult::RuntimeError;
+                        if let Some(&Value::Closure(getter)) =
+                            self.gc.deref(class).getters.get(&name)
+                        {
+                            self.call_closure(getter, 0)?;
+                            continue;
                         }
+
+                        self.bind_method(class, name)?;
                     } else {
-                        self.runtime_error("Only instances have properties.");
-                        return InterpretResult::RuntimeError;
+                        return Err(self.runtime_error("Only instances have properties."));
                     }
                 }
 
                 OpSetProperty(index) => {
                     if let Value::Instance(instance) = self.peek(1) {
                         let name = self.current_chunk().read_string(index);
+                        let class = self.gc.deref(instance).class;
+                        if let Some(&Value::Closure(setter)) =
+                            self.gc.deref(class).setters.get(&name)
+                        {
+                            self.call_closure(setter, 1)?;
+                            continue;
+                        }
+
                         let value = self.pop();
                         let instance = self.gc.deref_mut(instance);
                         instance.fields.insert(name, value);
                         self.pop();
                         self.push(value);
                     } else {
-                        self.runtime_error("Only instances have fields.");
-                        return InterpretResult::RuntimeError;
+                        return Err(self.runtime_error("Only instances have fields."));
                     }
                 }
                 OpGetSuper(index) => {
                     let name = self.current_chunk().read_string(index);
                     if let Value::Class(superclass) = self.pop() {
-                        if !self.bind_method(superclass, name) {
-                            return InterpretResult::RuntimeError;
-                        }
+                        self.bind_method(superclass, name)?;
                     } else {
-                        panic!("super found no class");
+                        return Err(self.runtime_error("Superclass must be a class."));
                     }
                 }
                 OpEqual => {
+                    if let Value::Instance(instance) = self.peek(1) {
+                        if let Some(method) = self.operator_method(instance, self.operators.equal) {
+                            self.call_closure(method, 1)?;
+                            continue;
+                        }
+                    }
+                    if let Value::Instance(instance) = self.peek(0) {
+                        if self
+                            .operator_method(instance, self.operators.equal)
+                            .is_some()
+                        {
+                            self.call_reflected_operator(instance, self.operators.equal)?;
+                            continue;
+                        }
+                    }
+
                     let b = self.pop();
                     let a = self.pop();
                     self.push((a == b).into());
                 }
-                OpGreater => binary_op!(self, >),
-                OpLess => binary_op!(self, <),
-                OpAdd => binary_op!(self, +),
-                OpSubtract => binary_op!(self, -),
-                OpMultiply => binary_op!(self, *),
-                OpDivide => binary_op!(self, /),
+                OpGreater => binary_op!(self, >, greater, less),
+                OpLess => binary_op!(self, <, less, greater),
+                OpAdd => binary_op!(self, +, add, reflected_add),
+                OpSubtract => binary_op!(self, -, subtract, reflected_subtract),
+                OpMultiply => binary_op!(self, *, multiply, reflected_multiply),
+                OpDivide => binary_op!(self, /, divide, reflected_divide),
                 OpNot => {
                     let value = self.pop().is_falsey();
                     self.push(value.into())
                 }
                 OpNegate => {
-                    if let Value::Number(value) = self.pop() {
+                    if let Value::Instance(instance) = self.peek(0) {
+                        self.call_operator(instance, self.operators.negate, 0)?;
+                    } else if let Value::Number(value) = self.pop() {
                         self.push((-value).into())
                     } else {
-                        self.runtime_error("Operand must be a number.");
-                        return InterpretResult::RuntimeError;
+                        return Err(self.runtime_error("Operand must be a number."));
                     }
                 }
                 OpPrint => {
-                    let value = self.pop();
-                    let formatter = GcTraceFormatter::new(value, &self.gc);
-                    println!("{}", formatter);
+                    let string = self.stringify(self.peek(0))?;
+                    self.pop();
+                    if let Err(error) = writeln!(self.output, "{}", string) {
+                        return Err(
+                            self.runtime_error(&format!("Could not write output: {}.", error))
+                        );
+                    }
                 }
                 OpJump(offset) => {
                     self.current_frame_mut().ip += offset as usize;
@@ -552,25 +1771,19 @@ impl VM {
                 }
                 OpCall(arg_count) => {
                     let value = self.peek(arg_count as usize);
-                    if !self.call_value(value, arg_count as usize) {
-                        return InterpretResult::RuntimeError;
-                    }
+                    self.call_value(value, arg_count as usize)?;
                 }
                 OpInvoke(name, arg_count) => {
                     let method = self.current_chunk().read_string(name);
-                    if !self.invoke(method, arg_count as usize) {
-                        return InterpretResult::RuntimeError;
-                    }
+                    self.invoke(method, arg_count as usize)?;
                     *self.current_frame_mut() = self.frames[self.frames.len() - 1].clone();
                 }
                 OpSuperInvoke(name, arg_count) => {
                     let method = self.current_chunk().read_string(name);
                     if let Value::Class(superclass) = self.pop() {
-                        if !self.invoke_from_class(superclass, method, arg_count as usize) {
-                            return InterpretResult::RuntimeError;
-                        }
+                        self.invoke_from_class(superclass, method, arg_count as usize)?;
                     } else {
-                        panic!("super invoke with no class");
+                        return Err(self.runtime_error("Superclass must be a class."));
                     }
 
                     *self.current_frame_mut() = self.frames[self.frames.len() - 1].clone();
@@ -608,13 +1821,20 @@ impl VM {
                     let slot = self.current_frame().slot;
                     self.close_upvalues(slot);
                     self.frames.pop();
-                    if self.frames.is_empty() {
-                        self.pop();
-                        return InterpretResult::Ok;
-                    }
-
                     self.stack.truncate(slot);
                     self.push(value);
+                    if self.frames.len() == depth {
+                        return Ok(());
+                    }
+                }
+                OpImport(index) => {
+                    let path = self.current_chunk().read_string(index);
+                    let module = self.import(path)?;
+                    self.push(Value::Module(module));
+                }
+                OpThrow => {
+                    let value = self.pop();
+                    return Err(self.thrown_error(value));
                 }
                 OpClass(index) => {
                     let name = self.current_chunk().read_string(index);
@@ -626,19 +1846,113 @@ impl VM {
                     if let Value::Class(superclass) = self.peek(1) {
                         let superclass = self.gc.deref(superclass);
                         let methods = superclass.methods.clone();
+                        let statics = superclass.statics.clone();
+                        let getters = superclass.getters.clone();
+                        let setters = superclass.setters.clone();
                         if let Value::Class(subclass) = self.peek(0) {
                             let subclass = self.gc.deref_mut(subclass);
                             subclass.methods.extend(methods);
+                            subclass.statics.extend(statics);
+                            subclass.getters.extend(getters);
+                            subclass.setters.extend(setters);
                             self.pop();
                         }
                     } else {
-                        self.runtime_error("Superclass must be a class.");
-                        return InterpretResult::RuntimeError;
+                        return Err(self.runtime_error("Superclass must be a class."));
                     }
                 }
                 OpMethod(index) => {
                     let name = self.current_chunk().read_string(index);
-                    self.define_method(name)
+                    self.define_method(name, |class| &mut class.methods)
+                }
+                OpStaticMethod(index) => {
+                    let name = self.current_chunk().read_string(index);
+                    self.define_method(name, |class| &mut class.statics)
+                }
+                OpGetter(index) => {
+                    let name = self.current_chunk().read_string(index);
+                    self.define_method(name, |class| &mut class.getters)
+                }
+                OpSetter(index) => {
+                    let name = self.current_chunk().read_string(index);
+                    self.define_method(name, |class| &mut class.setters)
+                }
+                OpBuildList(item_count) => {
+                    let start = self.stack.len() - item_count as usize;
+                    let items = self.stack[start..].to_vec();
+                    let list = self.alloc(List::new(items));
+                    self.stack.truncate(start);
+                    self.push(Value::List(list));
+                }
+                OpBuildMap(entry_count) => {
+                    let start = self.stack.len() - 2 * entry_count as usize;
+                    let mut map = Map::new();
+                    for i in (start..self.stack.len()).step_by(2) {
+                        let value = self.stack[i + 1];
+                        let key = self.map_key(self.stack[i])?;
+                        map.insert(key, value);
+                    }
+
+                    let map = self.alloc(map);
+                    self.stack.truncate(start);
+                    self.push(Value::Map(map));
+                }
+                OpBuildString(part_count) => {
+                    let start = self.stack.len() - part_count as usize;
+                    let mut string = String::new();
+                    for i in start..self.stack.len() {
+                        string += &self.stringify(self.stack[i])?;
+                    }
+
+                    let string = self.intern(string);
+                    self.stack.truncate(start);
+                    self.push(Value::String(string));
+                }
+                OpGetIndex => {
+                    let index = self.peek(0);
+                    let value = match self.peek(1) {
+                        Value::List(list) => {
+                            let index = self.list_index(list, index)?;
+                            self.gc.deref(list).items[index]
+                        }
+                        Value::Map(map) => {
+                            let key = self.map_key(index)?;
+                            self.gc.deref(map).get(key).unwrap_or_default()
+                        }
+                        Value::Instance(instance) => {
+                            self.call_operator(instance, self.operators.index, 1)?;
+                            continue;
+                        }
+                        _ => {
+                            return Err(self.runtime_error("Only lists and maps can be indexed."));
+                        }
+                    };
+
+                    self.pop();
+                    self.pop();
+                    self.push(value);
+                }
+                OpSetIndex => {
+                    let index = self.peek(1);
+                    let value = self.peek(0);
+                    match self.peek(2) {
+                        Value::List(list) => {
+                            let index = self.list_index(list, index)?;
+                            self.gc.deref_mut(list).items[index] = value;
+                        }
+                        Value::Map(map) => {
+                            let key = self.map_key(index)?;
+                            self.gc.deref_mut(map).insert(key, value);
+                        }
+                        _ => {
+                            return Err(self.runtime_error("Only lists and maps can be indexed."));
+                        }
+                    }
+
+                    self.pop();
+                    self.pop();
+                    self.pop();
+                    self.push(value);
                 }
             }
         }
@@ -650,3 +1964,51 @@ impl Default for VM {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `print` destination the test can read back after the VM is done.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run(source: &str) -> String {
+        let output = Output::default();
+        let mut vm = VM::builder()
+            .output(output.clone())
+            .diagnostics(io::sink())
+            .build();
+        vm.interpret(source).unwrap();
+        let bytes = output.0.borrow().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn print_keeps_collections_a_to_string_removed_alive() {
+        let output = run(r#"
+            class Clear {
+              toString() {
+                outer[0] = nil;
+                outer[1] = nil;
+                var garbage = nil;
+                for (var i = 0; i < 50000; i = i + 1) garbage = [garbage, i];
+                return "cleared";
+              }
+            }
+            var outer = [[Clear(), [1]], {"key": [2]}];
+            print outer;
+        "#);
+        assert_eq!(output, "[[cleared, [1]], nil]\n");
+    }
+}